@@ -0,0 +1,194 @@
+use nih_plug::prelude::Enum;
+
+use crate::shaper::Shaper;
+
+/// Below this difference between inputs the antiderivative quotients become unreliable, and the
+/// curve is evaluated directly instead.
+const TOLERANCE: f64 = 1e-5;
+
+/// Antiderivative anti-aliasing, which suppresses aliasing without needing as much oversampling.
+/// This always works on the linearly interpolated table, the interpolation and exact modes are not
+/// used while it is enabled.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdaaOrder {
+    #[name = "Off"]
+    Off,
+    /// Delays the signal by half a sample.
+    #[name = "1st Order"]
+    First,
+    /// Suppresses more aliasing, at the cost of some treble and a delay of one sample.
+    #[name = "2nd Order"]
+    Second,
+}
+
+impl AdaaOrder {
+    /// The largest value [`latency()`][Self::latency()] can return.
+    pub const MAX_LATENCY: u32 = 1;
+
+    /// The delay in samples at the base rate when running at `oversampling_factor`. This is a
+    /// fraction of a sample for most settings.
+    pub fn delay(self, oversampling_factor: usize) -> f32 {
+        let half_samples = match self {
+            AdaaOrder::Off => 0,
+            AdaaOrder::First => 1,
+            AdaaOrder::Second => 2,
+        };
+        half_samples as f32 / (2 * oversampling_factor) as f32
+    }
+
+    /// [`delay()`][Self::delay()] rounded to the nearest whole sample, for reporting to the host.
+    /// The dry signal is delayed by the exact amount instead.
+    pub fn latency(self, oversampling_factor: usize) -> u32 {
+        self.delay(oversampling_factor).round() as u32
+    }
+}
+
+/// The input history of one channel.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Adaa {
+    x1: f64,
+    x2: f64,
+    x3: f64,
+}
+
+impl Adaa {
+    /// Adds the next input sample. Call this once per sample before
+    /// [`process()`][Self::process()].
+    pub fn push(&mut self, x: f32) {
+        self.x3 = self.x2;
+        self.x2 = self.x1;
+        self.x1 = x as f64;
+    }
+
+    /// Shapes the most recently pushed sample. This can be called for multiple shapers in a row,
+    /// for instance while crossfading.
+    pub fn process(&self, order: AdaaOrder, shaper: &Shaper) -> f32 {
+        let (x1, x2, x3) = (self.x1, self.x2, self.x3);
+        let covered = |inputs: &[f64]| inputs.iter().all(|&x| shaper.has_antiderivatives_at(x));
+        let y = match order {
+            AdaaOrder::First if covered(&[x1, x2]) => first_order(shaper, x1, x2),
+            AdaaOrder::Second if covered(&[x1, x2, x3]) => second_order(shaper, x1, x2, x3),
+            // This includes mirrored or wrapped inputs outside of the domain
+            _ => return shaper.process(x1 as f32),
+        };
+        y as f32
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+fn first_order(shaper: &Shaper, x1: f64, x2: f64) -> f64 {
+    let delta = x1 - x2;
+    if delta.abs() < TOLERANCE {
+        shaper.process(((x1 + x2) / 2.0) as f32) as f64
+    } else {
+        (shaper.antiderivative(x1) - shaper.antiderivative(x2)) / delta
+    }
+}
+
+/// Second order ADAA as described by Bilbao et al., "Antiderivative Antialiasing for Memoryless
+/// Nonlinearities", with their fallback for inputs that barely change.
+fn second_order(shaper: &Shaper, x1: f64, x2: f64, x3: f64) -> f64 {
+    let delta = x1 - x3;
+    if delta.abs() < TOLERANCE {
+        let mean = (x1 + x3) / 2.0;
+        let delta = mean - x2;
+        return if delta.abs() < TOLERANCE {
+            shaper.process(((mean + x2) / 2.0) as f32) as f64
+        } else {
+            2.0 / delta
+                * (shaper.antiderivative(mean)
+                    + (shaper.second_antiderivative(x2) - shaper.second_antiderivative(mean))
+                        / delta)
+        };
+    }
+
+    2.0 / delta * (quotient(shaper, x1, x2) - quotient(shaper, x2, x3))
+}
+
+/// The difference quotient of the second antiderivative, or the first antiderivative in the
+/// middle if the inputs are too close.
+fn quotient(shaper: &Shaper, a: f64, b: f64) -> f64 {
+    let delta = a - b;
+    if delta.abs() < TOLERANCE {
+        shaper.antiderivative((a + b) / 2.0)
+    } else {
+        (shaper.second_antiderivative(a) - shaper.second_antiderivative(b)) / delta
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shaper::tests::{assert_close, shaper};
+    use crate::shaper::Extrapolation;
+
+    fn antiderivatives(prompt: &str) -> Shaper {
+        let mut shaper = shaper(prompt, 2.0);
+        shaper.update_antiderivatives();
+        shaper
+    }
+
+    fn adaa(inputs: &[f32]) -> Adaa {
+        let mut adaa = Adaa::default();
+        for &x in inputs {
+            adaa.push(x);
+        }
+        adaa
+    }
+
+    #[test]
+    fn latency() {
+        assert_eq!(AdaaOrder::Off.latency(1), 0);
+        assert_eq!(AdaaOrder::First.latency(1), 1);
+        assert_eq!(AdaaOrder::Second.latency(1), 1);
+        assert_eq!(AdaaOrder::Second.latency(2), 1);
+        assert_eq!(AdaaOrder::Second.latency(4), 0);
+        assert_eq!(AdaaOrder::First.delay(1), 0.5);
+        assert_eq!(AdaaOrder::Second.delay(4), 0.25);
+    }
+
+    #[test]
+    fn lines_are_averaged() {
+        let shaper = antiderivatives("x");
+        let first = adaa(&[0.2, 0.6]).process(AdaaOrder::First, &shaper);
+        assert_close(first, 0.4, 1e-3);
+        let second = adaa(&[0.0, 0.3, 0.9]).process(AdaaOrder::Second, &shaper);
+        assert_close(second, 0.4, 1e-3);
+    }
+
+    #[test]
+    fn constant_inputs_match_the_curve() {
+        let shaper = antiderivatives("math::sin(3 * x)");
+        let adaa = adaa(&[0.5, 0.5, 0.5]);
+        for order in [AdaaOrder::Off, AdaaOrder::First, AdaaOrder::Second] {
+            assert_close(adaa.process(order, &shaper), 1.5f64.sin(), 1e-3);
+        }
+    }
+
+    #[test]
+    fn antiderivatives_match_the_curve() {
+        let mut shaper = antiderivatives("x^2 - x");
+        shaper.set_extrapolation(Extrapolation::Linear);
+        let h = 1e-3;
+        for x in [-3.0, -1.5, -0.7, 0.3, 1.2, 2.5] {
+            let derivative =
+                (shaper.antiderivative(x + h) - shaper.antiderivative(x - h)) / (2.0 * h);
+            assert_close(derivative, shaper.process(x as f32), 1e-3);
+            let second_derivative = (shaper.second_antiderivative(x + h)
+                - shaper.second_antiderivative(x - h))
+                / (2.0 * h);
+            assert_close(second_derivative, shaper.antiderivative(x), 1e-3);
+        }
+    }
+
+    #[test]
+    fn folded_inputs_are_not_antialiased() {
+        let mut shaper = antiderivatives("x");
+        shaper.set_extrapolation(Extrapolation::Mirror);
+        let first = adaa(&[1.0, 3.0]).process(AdaaOrder::First, &shaper);
+        assert_eq!(first, shaper.process(3.0));
+    }
+}
@@ -0,0 +1,194 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::ShaperError;
+use crate::math::harmonics;
+use crate::shaper::{
+    Extrapolation, Interpolation, Macros, NonFinitePolicy, Normalization, Shaper,
+    DEFAULT_TABLE_SIZE, MAX_INPUT_MAX, MAX_TABLE_SIZE, MIN_INPUT_MAX, MIN_TABLE_SIZE,
+};
+
+/// Everything needed to rebuild a shaping curve. This is persisted with the plugin state so a
+/// reopened project gets its curve back without the editor ever being opened.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CurveSettings {
+    pub expression: String,
+    #[serde(
+        alias = "normalize",
+        deserialize_with = "Normalization::deserialize_legacy"
+    )]
+    pub normalization: Normalization,
+    pub non_finite: NonFinitePolicy,
+    pub interpolation: Interpolation,
+    /// The number of table entries, clamped to the supported range when building.
+    pub table_size: usize,
+    /// The curve is defined for inputs from `-input_max` to `input_max`.
+    pub input_max: f32,
+    pub extrapolation: Extrapolation,
+    /// Shifts the pieces of a piecewise curve so they meet at their boundaries.
+    pub continuous: bool,
+    /// The harmonic designer's amplitudes, starting at the fundamental. Editing these replaces
+    /// the expression with the matching Chebyshev series, unless it was written by hand.
+    pub harmonics: Vec<f32>,
+}
+
+impl Default for CurveSettings {
+    fn default() -> Self {
+        Self {
+            expression: "x".to_owned(),
+            normalization: Normalization::default(),
+            non_finite: NonFinitePolicy::default(),
+            interpolation: Interpolation::default(),
+            table_size: DEFAULT_TABLE_SIZE,
+            input_max: MIN_INPUT_MAX,
+            extrapolation: Extrapolation::default(),
+            continuous: false,
+            harmonics: harmonics::default_amplitudes(),
+        }
+    }
+}
+
+impl CurveSettings {
+    /// Generates a new shaper table from these settings, the current macro values and the host's
+    /// sample rate. Gives up with [`ShaperError::Cancelled`] once `cancel` returns true.
+    pub fn build(
+        &self,
+        macros: &Macros,
+        sample_rate: f32,
+        cancel: &dyn Fn() -> bool,
+    ) -> Result<Shaper, ShaperError> {
+        let mut shaper = Shaper::new(
+            &self.expression,
+            self.table_size,
+            self.input_max,
+            macros,
+            sample_rate,
+            self.continuous,
+            cancel,
+        )?;
+        shaper.validate(self.non_finite)?;
+        shaper.normalize(self.normalization);
+        shaper.set_interpolation(self.interpolation);
+        shaper.set_extrapolation(self.extrapolation);
+        shaper.update_antiderivatives();
+        Ok(shaper)
+    }
+
+    /// Whether the expression can be replaced by the harmonic designer without losing anything,
+    /// which is the case for the default expression and for the series the designer generated.
+    pub fn uses_designer(&self) -> bool {
+        self.expression == harmonics::expression(&self.harmonics)
+            || self.expression == Self::default().expression
+    }
+
+    /// Replaces the harmonic amplitudes, starting at the fundamental, and the expression with the
+    /// resulting Chebyshev series. Hand-written expressions are left alone, in which case this
+    /// returns false and nothing changes.
+    pub fn set_harmonics(&mut self, amplitudes: Vec<f32>) -> bool {
+        if !self.uses_designer() {
+            return false;
+        }
+        self.harmonics = amplitudes;
+        self.expression = harmonics::expression(&self.harmonics);
+        true
+    }
+
+    /// Doubles the table size, wrapping around to the smallest one after the largest.
+    pub fn cycle_table_size(&mut self) {
+        self.table_size = if self.table_size >= MAX_TABLE_SIZE {
+            MIN_TABLE_SIZE
+        } else {
+            (self.table_size * 2).clamp(MIN_TABLE_SIZE, MAX_TABLE_SIZE)
+        };
+    }
+
+    /// Doubles the input domain, wrapping around to the smallest one after the largest.
+    pub fn cycle_input_max(&mut self) {
+        self.input_max = if self.input_max >= MAX_INPUT_MAX {
+            MIN_INPUT_MAX
+        } else {
+            (self.input_max * 2.0).clamp(MIN_INPUT_MAX, MAX_INPUT_MAX)
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let curve = CurveSettings {
+            expression: "math::tanh(3 * x)".to_owned(),
+            ..CurveSettings::default()
+        };
+        let json = serde_json::to_string(&curve).unwrap();
+        assert_eq!(serde_json::from_str::<CurveSettings>(&json).unwrap(), curve);
+    }
+
+    #[test]
+    fn legacy_state() {
+        // State saved before a setting existed loads with that setting's default, and settings
+        // that don't exist anymore are ignored
+        let curve: CurveSettings = serde_json::from_str(r#"{"expression": "x^3"}"#).unwrap();
+        let expected = CurveSettings {
+            expression: "x^3".to_owned(),
+            ..CurveSettings::default()
+        };
+        assert_eq!(curve, expected);
+        let curve: CurveSettings =
+            serde_json::from_str(r#"{"expression": "x", "removed": 1}"#).unwrap();
+        assert_eq!(curve, CurveSettings::default());
+    }
+
+    #[test]
+    fn legacy_normalize_flag() {
+        let curve: CurveSettings = serde_json::from_str(r#"{"normalize": true}"#).unwrap();
+        assert_eq!(curve.normalization, Normalization::Peak);
+        let curve: CurveSettings = serde_json::from_str(r#"{"normalize": false}"#).unwrap();
+        assert_eq!(curve.normalization, Normalization::Off);
+    }
+
+    #[test]
+    fn cancelled_build() {
+        let cancelled = CurveSettings::default().build(&Macros::default(), 48000.0, &|| true);
+        assert!(matches!(cancelled, Err(ShaperError::Cancelled)));
+    }
+
+    #[test]
+    fn harmonics_keep_hand_written_expressions() {
+        let mut curve = CurveSettings::default();
+        assert!(curve.set_harmonics(vec![0.5, 0.0, 0.25]));
+        assert_eq!(curve.expression, "0.5 * Cheb(x, 1) + 0.25 * Cheb(x, 3)");
+        // Designed curves can be edited again
+        assert!(curve.set_harmonics(vec![1.0]));
+        assert_eq!(curve.expression, "1 * Cheb(x, 1)");
+
+        curve.expression = "math::tanh(3 * x)".to_owned();
+        assert!(!curve.set_harmonics(vec![0.5]));
+        assert_eq!(curve.expression, "math::tanh(3 * x)");
+        assert_eq!(curve.harmonics, [1.0]);
+    }
+
+    #[test]
+    fn table_size() {
+        let mut curve = CurveSettings::default();
+        let sizes: Vec<usize> = (0..9)
+            .map(|_| {
+                curve.cycle_table_size();
+                curve.table_size
+            })
+            .collect();
+        assert_eq!(
+            sizes,
+            [1024, 2048, 4096, 8192, 16384, 32768, 65536, 256, 512]
+        );
+        let shaper = curve.build(&Macros::default(), 48000.0, &|| false).unwrap();
+        assert_eq!(shaper.size(), 512);
+
+        // Sizes from elsewhere get clamped
+        curve.table_size = 3;
+        let shaper = curve.build(&Macros::default(), 48000.0, &|| false).unwrap();
+        assert_eq!(shaper.size(), MIN_TABLE_SIZE);
+    }
+}
@@ -0,0 +1,93 @@
+/// A delay line with a fixed maximum length, used to keep the dry signal in line with the
+/// oversampled wet signal.
+pub struct DelayLine {
+    buffer: Box<[f32]>,
+    position: usize,
+}
+
+impl Default for DelayLine {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl DelayLine {
+    pub fn new(max_delay: usize) -> Self {
+        Self {
+            buffer: vec![0.0; max_delay + 1].into_boxed_slice(),
+            position: 0,
+        }
+    }
+
+    /// Pushes `sample` and returns the sample from `delay` samples ago. `delay` is clamped to the
+    /// maximum delay.
+    pub fn process(&mut self, sample: f32, delay: usize) -> f32 {
+        self.process_fractional(sample, delay as f32)
+    }
+
+    /// Like [`process()`][Self::process()], but linearly interpolates between the two samples
+    /// around a fractional `delay`.
+    pub fn process_fractional(&mut self, sample: f32, delay: f32) -> f32 {
+        let len = self.buffer.len();
+        self.buffer[self.position] = sample;
+        let delay = delay.clamp(0.0, (len - 1) as f32);
+        let whole = delay as usize;
+        let fraction = delay - whole as f32;
+        let tap = |delay: usize| self.buffer[(self.position + len - delay.min(len - 1)) % len];
+        let delayed = tap(whole) + (tap(whole + 1) - tap(whole)) * fraction;
+        self.position = (self.position + 1) % len;
+        delayed
+    }
+
+    pub fn reset(&mut self) {
+        self.buffer.fill(0.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `1, 2, 3, ...` through `delay`.
+    fn ramp(delay: &mut DelayLine, samples: usize, amount: usize) -> Vec<f32> {
+        (1..=samples)
+            .map(|i| delay.process(i as f32, amount))
+            .collect()
+    }
+
+    #[test]
+    fn delays() {
+        assert_eq!(ramp(&mut DelayLine::new(4), 4, 0), [1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(
+            ramp(&mut DelayLine::new(4), 6, 2),
+            [0.0, 0.0, 1.0, 2.0, 3.0, 4.0]
+        );
+        assert_eq!(
+            ramp(&mut DelayLine::new(4), 6, 4),
+            [0.0, 0.0, 0.0, 0.0, 1.0, 2.0]
+        );
+    }
+
+    #[test]
+    fn delay_is_clamped() {
+        assert_eq!(ramp(&mut DelayLine::new(2), 4, 10), [0.0, 0.0, 1.0, 2.0]);
+        assert_eq!(ramp(&mut DelayLine::default(), 2, 1), [1.0, 2.0]);
+    }
+
+    #[test]
+    fn fractional_delays() {
+        let mut delay = DelayLine::new(2);
+        let delayed: Vec<f32> = (1..=4)
+            .map(|i| delay.process_fractional(i as f32, 1.5))
+            .collect();
+        assert_eq!(delayed, [0.0, 0.5, 1.5, 2.5]);
+    }
+
+    #[test]
+    fn reset() {
+        let mut delay = DelayLine::new(2);
+        ramp(&mut delay, 2, 2);
+        delay.reset();
+        assert_eq!(ramp(&mut delay, 3, 2), [0.0, 0.0, 1.0]);
+    }
+}
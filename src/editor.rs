@@ -1,63 +1,145 @@
 use nih_plug::log::debug;
-use nih_plug::prelude::{AtomicF32, Editor};
+use nih_plug::prelude::{AsyncExecutor, AtomicF32, Editor};
 use nih_plug_vizia::vizia::prelude::*;
 
+use harmonics_view::HarmonicsView;
 use nih_plug_vizia::{create_vizia_editor, ViziaState, ViziaTheming};
 use shaper_view::ShaperView;
-use std::fs::File;
-use std::io::{BufReader, Read};
+use spectrum_view::SpectrumView;
 use std::sync::{Arc, Mutex};
 
-use crate::MathshaperParams;
-
-use crate::shaper::Shaper as GenericShaper;
-const TABLE_SIZE: usize = 512;
-type DisplayShaper = GenericShaper<TABLE_SIZE>;
-use crate::Shaper as DspShaper;
+use crate::adaa::AdaaOrder;
+use crate::curve::CurveSettings;
+use crate::generator::Generator;
+use crate::math::harmonics::Spectra;
+use crate::prompt_file::PromptFile;
+use crate::shaper::Shaper;
+use crate::{Mathshaper, MathshaperParams, Task};
 
+mod harmonics_view;
 mod shaper_view;
+mod spectrum_view;
 
 #[derive(Lens)]
 struct Data {
-    _params: Arc<MathshaperParams>,
-    shaper: Arc<Mutex<DisplayShaper>>,
+    params: Arc<MathshaperParams>,
+    shaper: Arc<Mutex<Shaper>>,
+    spectra: Arc<Mutex<Spectra>>,
     peak_max: Arc<AtomicF32>,
     peak_min: Arc<AtomicF32>,
-    shaper_input_data: Arc<Mutex<triple_buffer::Input<DspShaper>>>,
+    generator: Arc<Generator>,
+    executor: AsyncExecutor<Mathshaper>,
+    prompt_file: Arc<PromptFile>,
+    /// The path currently in the prompt file text box.
+    prompt_path: String,
+    /// The expression currently in the text box, this may differ from the committed one.
+    expression: String,
+    /// The generator's settings, updated on [`EditorEvent::Refresh`].
+    settings: CurveSettings,
+    /// The generator's message, updated along with the settings.
+    message: String,
 }
 
 enum EditorEvent {
+    /// The generator's settings or message changed.
+    Refresh,
     Generate,
+    SetPromptPath(String),
+    ToggleWatch,
+    SetExpression(String),
+    Commit,
     Normalize,
+    CycleNonFinitePolicy,
+    CycleInterpolation,
+    CycleTableSize,
+    CycleInputMax,
+    CycleExtrapolation,
+    ToggleContinuity,
+    SetHarmonics(Vec<f32>),
+}
+
+impl Data {
+    /// Hands `curve` to the background generator. The settings are only stored if they build
+    /// successfully, otherwise the last good curve keeps playing.
+    fn apply(&self, curve: CurveSettings) {
+        let id = self.generator.commit(curve);
+        self.executor.execute_background(Task::Generate(id));
+    }
 }
 
 impl Model for Data {
     fn event(&mut self, _cx: &mut EventContext, event: &mut Event) {
         event.map(|event: &EditorEvent, _| match event {
+            EditorEvent::Refresh => {
+                self.settings = self.generator.settings();
+                self.message = self.generator.message();
+            }
             EditorEvent::Generate => {
-                let text_file = File::open(std::env!("TEXT_INPUT_PATH")).unwrap();
-                let mut reader = BufReader::new(text_file);
-                let mut prompt = String::new();
-                reader.read_to_string(&mut prompt).unwrap();
-
-                println!("Prompt: {prompt}");
-
-                let mut lock = self.shaper.lock().unwrap(); // TODO: Error Handling Poison Error
-                lock.prompt(&prompt).unwrap(); // TODO: Error Handling Prompt Error
-
-                let mut lock = self.shaper_input_data.lock().unwrap();
-                let shaper_input = lock.input_buffer();
-                shaper_input.prompt(&prompt).unwrap(); // TODO: Error Handling Prompt Error
-                lock.publish();
+                if let Some(id) = self.prompt_file.load() {
+                    self.expression = self.generator.settings().expression;
+                    self.executor.execute_background(Task::Generate(id));
+                }
+            }
+            EditorEvent::SetPromptPath(path) => {
+                self.prompt_path = path.clone();
+                self.prompt_file.set_path(path.clone());
+            }
+            EditorEvent::ToggleWatch => {
+                let watch = !self.prompt_file.settings().watch;
+                self.prompt_file.set_watch(watch);
+            }
+            EditorEvent::SetExpression(expression) => self.expression = expression.clone(),
+            EditorEvent::Commit => {
+                let mut curve = self.generator.settings();
+                curve.expression = self.expression.clone();
+                self.apply(curve);
             }
             EditorEvent::Normalize => {
-                let mut lock = self.shaper.lock().unwrap(); // TODO: Error Handling Poison Error
-                lock.normalize();
-
-                let mut lock = self.shaper_input_data.lock().unwrap(); // TODO: Error Handling Poison Error
-                let shaper_input = lock.input_buffer();
-                shaper_input.normalize();
-                lock.publish();
+                let mut curve = self.generator.settings();
+                curve.normalization = curve.normalization.next();
+                self.apply(curve);
+            }
+            EditorEvent::CycleNonFinitePolicy => {
+                let mut curve = self.generator.settings();
+                curve.non_finite = curve.non_finite.next();
+                self.apply(curve);
+            }
+            EditorEvent::CycleInterpolation => {
+                let mut curve = self.generator.settings();
+                curve.interpolation = curve.interpolation.next();
+                self.apply(curve);
+            }
+            EditorEvent::CycleTableSize => {
+                let mut curve = self.generator.settings();
+                curve.cycle_table_size();
+                self.apply(curve);
+            }
+            EditorEvent::CycleInputMax => {
+                let mut curve = self.generator.settings();
+                curve.cycle_input_max();
+                self.apply(curve);
+            }
+            EditorEvent::CycleExtrapolation => {
+                let mut curve = self.generator.settings();
+                curve.extrapolation = curve.extrapolation.next();
+                self.apply(curve);
+            }
+            EditorEvent::ToggleContinuity => {
+                let mut curve = self.generator.settings();
+                curve.continuous = !curve.continuous;
+                self.apply(curve);
+            }
+            EditorEvent::SetHarmonics(amplitudes) => {
+                let mut curve = self.generator.settings();
+                if curve.set_harmonics(amplitudes.clone()) {
+                    self.expression = curve.expression.clone();
+                    self.apply(curve);
+                } else {
+                    self.generator.set_message(String::from(
+                        "The harmonics don't replace hand-written expressions, set the expression \
+                         to x to start a new design",
+                    ));
+                }
             }
         })
     }
@@ -73,7 +155,9 @@ pub(crate) fn create(
     editor_state: Arc<ViziaState>,
     peak_max: Arc<AtomicF32>,
     peak_min: Arc<AtomicF32>,
-    shaper_input_data: Arc<Mutex<triple_buffer::Input<DspShaper>>>,
+    generator: Arc<Generator>,
+    prompt_file: Arc<PromptFile>,
+    executor: AsyncExecutor<Mathshaper>,
 ) -> Option<Box<dyn Editor>> {
     create_vizia_editor(editor_state, ViziaTheming::Custom, move |cx, _| {
         debug!("Creating view...");
@@ -82,19 +166,34 @@ pub(crate) fn create(
         cx.add_stylesheet(include_style!("src/style.css"))
             .expect("Failed to load stylesheet");
 
-        let shaper = Arc::new(Mutex::default());
         Data {
-            _params: params.clone(),
-            shaper: shaper.clone(),
+            params: params.clone(),
+            shaper: generator.display_shaper.clone(),
+            spectra: generator.display_spectra.clone(),
             peak_max: peak_max.clone(),
             peak_min: peak_min.clone(),
-            shaper_input_data: shaper_input_data.clone(),
+            generator: generator.clone(),
+            executor: executor.clone(),
+            prompt_file: prompt_file.clone(),
+            prompt_path: prompt_file.settings().path,
+            expression: generator.settings().expression,
+            settings: generator.settings(),
+            message: generator.message(),
         }
         .build(cx);
 
+        // Builds finish on a background thread, the proxy gets the results to the editor
+        let mut proxy = cx.get_proxy();
+        generator.set_listener(Some(Box::new(move || {
+            let _ = proxy.emit(EditorEvent::Refresh);
+        })));
+
         HStack::new(cx, move |cx| {
             VStack::new(cx, move |cx| {
                 Label::new(cx, "PRE");
+                Textbox::new(cx, Data::prompt_path)
+                    .on_submit(|cx, text, _| cx.emit(EditorEvent::SetPromptPath(text)))
+                    .class("path-input");
                 Button::new(
                     cx,
                     |cx| {
@@ -102,10 +201,121 @@ pub(crate) fn create(
                     },
                     |cx| Label::new(cx, "Reload"),
                 );
+                Button::new(
+                    cx,
+                    |cx| cx.emit(EditorEvent::ToggleWatch),
+                    |cx| {
+                        Label::new(
+                            cx,
+                            Data::prompt_file.map(|prompt_file| {
+                                if prompt_file.settings().watch {
+                                    "Watch: On"
+                                } else {
+                                    "Watch: Off"
+                                }
+                            }),
+                        )
+                    },
+                );
+                Button::new(
+                    cx,
+                    |cx| cx.emit(EditorEvent::Commit),
+                    |cx| Label::new(cx, "Apply"),
+                );
                 Button::new(
                     cx,
                     |cx| cx.emit(EditorEvent::Normalize),
-                    |cx| Label::new(cx, "Normalize"),
+                    |cx| {
+                        Label::new(
+                            cx,
+                            Data::settings
+                                .map(|settings| format!("Normalize: {}", settings.normalization)),
+                        )
+                    },
+                );
+                Button::new(
+                    cx,
+                    |cx| cx.emit(EditorEvent::CycleNonFinitePolicy),
+                    |cx| {
+                        Label::new(
+                            cx,
+                            Data::settings
+                                .map(|settings| format!("NaN/Inf: {}", settings.non_finite)),
+                        )
+                    },
+                );
+                // ADAA always uses the linearly interpolated table
+                Binding::new(
+                    cx,
+                    Data::params.map(|params| params.adaa.value() != AdaaOrder::Off),
+                    |cx, adaa| {
+                        let adaa = adaa.get(cx);
+                        Button::new(
+                            cx,
+                            |cx| cx.emit(EditorEvent::CycleInterpolation),
+                            move |cx| {
+                                Label::new(
+                                    cx,
+                                    Data::settings.map(move |settings| {
+                                        if adaa {
+                                            String::from("Interp: n/a (ADAA)")
+                                        } else {
+                                            format!("Interp: {}", settings.interpolation)
+                                        }
+                                    }),
+                                )
+                            },
+                        )
+                        .disabled(adaa);
+                    },
+                );
+                Button::new(
+                    cx,
+                    |cx| cx.emit(EditorEvent::CycleTableSize),
+                    |cx| {
+                        Label::new(
+                            cx,
+                            Data::settings.map(|settings| format!("Size: {}", settings.table_size)),
+                        )
+                    },
+                );
+                Button::new(
+                    cx,
+                    |cx| cx.emit(EditorEvent::CycleInputMax),
+                    |cx| {
+                        Label::new(
+                            cx,
+                            Data::settings
+                                .map(|settings| format!("Domain: ±{}", settings.input_max)),
+                        )
+                    },
+                );
+                Button::new(
+                    cx,
+                    |cx| cx.emit(EditorEvent::CycleExtrapolation),
+                    |cx| {
+                        Label::new(
+                            cx,
+                            Data::settings
+                                .map(|settings| format!("Outside: {}", settings.extrapolation)),
+                        )
+                    },
+                );
+                Button::new(
+                    cx,
+                    |cx| cx.emit(EditorEvent::ToggleContinuity),
+                    |cx| {
+                        Label::new(
+                            cx,
+                            Data::settings.map(|settings| {
+                                if settings.continuous {
+                                    "Continuous: On"
+                                } else {
+                                    "Continuous: Off"
+                                }
+                            }),
+                        )
+                    },
                 );
             })
             .class("side-container");
@@ -113,11 +323,21 @@ pub(crate) fn create(
             VStack::new(cx, move |cx| {
                 ShaperView::new(cx, Data::shaper, Data::peak_max, Data::peak_min);
                 // TODO: Resizing layout, keep at square
+                Textbox::new_multiline(cx, Data::expression, true)
+                    .on_edit(|cx, text| cx.emit(EditorEvent::SetExpression(text)))
+                    .on_submit(|cx, _, _| cx.emit(EditorEvent::Commit))
+                    .class("expression-input");
+                Label::new(cx, Data::message).class("error-label");
             })
             .class("main-container");
 
             VStack::new(cx, |cx| {
                 Label::new(cx, "POST");
+                Label::new(cx, "Harmonics");
+                HarmonicsView::new(cx, Data::generator).class("harmonics-view");
+                Label::new(cx, "Predicted Spectrum");
+                SpectrumView::new(cx, Data::spectra, Data::peak_max, Data::peak_min)
+                    .class("harmonics-view");
             })
             .class("side-container");
         })
@@ -0,0 +1,113 @@
+use std::sync::Arc;
+
+use nih_plug_vizia::vizia::{
+    prelude::*,
+    vg::{self, Color},
+};
+
+use crate::editor::EditorEvent;
+use crate::generator::Generator;
+use crate::math::harmonics;
+
+/// A bar per harmonic. Dragging a bar up or down from the center line sets that harmonic's
+/// amplitude, below the center line the partial's phase is flipped. The new design is only
+/// committed once the mouse is released.
+pub struct HarmonicsView {
+    generator: Arc<Generator>,
+    /// The amplitudes being edited while dragging.
+    drag: Option<Vec<f32>>,
+}
+
+impl HarmonicsView {
+    pub fn new<LGenerator>(cx: &mut Context, generator: LGenerator) -> Handle<Self>
+    where
+        LGenerator: Lens<Target = Arc<Generator>>,
+    {
+        Self {
+            generator: generator.get(cx),
+            drag: None,
+        }
+        .build(cx, |_cx| ())
+    }
+
+    /// Sets the harmonic under the cursor to the amplitude the cursor's height corresponds to.
+    fn set_from_cursor(&mut self, cx: &mut EventContext, x: f32, y: f32) {
+        let bounds = cx.bounds();
+        let Some(amplitudes) = &mut self.drag else {
+            return;
+        };
+        if bounds.w <= 0.0 || bounds.h <= 0.0 {
+            return;
+        }
+
+        let index = (((x - bounds.x) / bounds.w) * amplitudes.len() as f32).floor();
+        let index = index.clamp(0.0, (amplitudes.len() - 1) as f32) as usize;
+        amplitudes[index] = (1.0 - 2.0 * (y - bounds.y) / bounds.h).clamp(-1.0, 1.0);
+        cx.needs_redraw();
+    }
+}
+
+impl View for HarmonicsView {
+    fn element(&self) -> Option<&'static str> {
+        Some("harmonics_view")
+    }
+
+    fn event(&mut self, cx: &mut EventContext, event: &mut Event) {
+        event.map(|window_event, meta| match window_event {
+            WindowEvent::MouseDown(MouseButton::Left) => {
+                let mut amplitudes = self.generator.settings().harmonics;
+                amplitudes.resize(harmonics::NUM_HARMONICS, 0.0);
+                self.drag = Some(amplitudes);
+                cx.capture();
+                let (x, y) = (cx.mouse().cursorx, cx.mouse().cursory);
+                self.set_from_cursor(cx, x, y);
+                meta.consume();
+            }
+            WindowEvent::MouseMove(x, y) => {
+                self.set_from_cursor(cx, *x, *y);
+            }
+            WindowEvent::MouseUp(MouseButton::Left) => {
+                if let Some(amplitudes) = self.drag.take() {
+                    cx.emit(EditorEvent::SetHarmonics(amplitudes));
+                }
+                cx.release();
+                cx.needs_redraw();
+            }
+            _ => {}
+        });
+    }
+
+    fn draw(&self, cx: &mut DrawContext, canvas: &mut Canvas) {
+        let bounds = cx.bounds();
+        let amplitudes = match &self.drag {
+            Some(amplitudes) => amplitudes.clone(),
+            None => self.generator.settings().harmonics,
+        };
+        if amplitudes.is_empty() {
+            return;
+        }
+
+        let center = bounds.y + bounds.h / 2.0;
+        let bar_width = bounds.w / amplitudes.len() as f32;
+        let mut bars = vg::Path::new();
+        for (i, amplitude) in amplitudes.iter().enumerate() {
+            let height = amplitude * bounds.h / 2.0;
+            bars.rect(
+                bounds.x + i as f32 * bar_width + 1.0,
+                center - height.max(0.0),
+                (bar_width - 2.0).max(1.0),
+                height.abs(),
+            );
+        }
+        canvas.fill_path(&bars, &vg::Paint::color(Color::rgb(0, 255, 0)));
+
+        let line_width = cx.scale_factor() * 1.0;
+        let mut axis = vg::Path::new();
+        axis.move_to(bounds.x, center);
+        axis.line_to(bounds.x + bounds.w, center);
+        canvas.stroke_path(
+            &axis,
+            &vg::Paint::color(Color::rgb(255, 255, 255)).with_line_width(line_width),
+        );
+    }
+}
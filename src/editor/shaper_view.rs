@@ -6,10 +6,10 @@ use nih_plug_vizia::vizia::{
     vg::{self, Color},
 };
 
-use crate::editor::DisplayShaper;
+use crate::shaper::Shaper;
 
 pub struct ShaperView {
-    shaper: Arc<Mutex<DisplayShaper>>,
+    shaper: Arc<Mutex<Shaper>>,
     peak_max: Arc<AtomicF32>,
     peak_min: Arc<AtomicF32>,
 }
@@ -22,7 +22,7 @@ impl ShaperView {
         peak_min: LPeakMin,
     ) -> Handle<Self>
     where
-        LShaper: Lens<Target = Arc<Mutex<DisplayShaper>>>,
+        LShaper: Lens<Target = Arc<Mutex<Shaper>>>,
         LPeakMax: Lens<Target = Arc<AtomicF32>>,
         LPeakMin: Lens<Target = Arc<AtomicF32>>,
     {
@@ -55,13 +55,16 @@ impl View for ShaperView {
         // Draw Plot
         let lock = self.shaper.lock().unwrap(); // TODO: Error Handling
         lock.display(cx, canvas);
+        let input_max = lock.input_max();
 
         // Draw Peaks
         let peaks_paint =
             vg::Paint::color(Color::rgba(0, 255, 255, 64)).with_line_width(line_width);
         let mut peaks = vg::Path::new();
-        let x_max = bounds.x + (bounds.w / 2.0) * (1.0 + self.peak_max.load(Ordering::Relaxed));
-        let x_min = bounds.x + (bounds.w / 2.0) * (1.0 + self.peak_min.load(Ordering::Relaxed));
+        let x_max =
+            bounds.x + (bounds.w / 2.0) * (1.0 + self.peak_max.load(Ordering::Relaxed) / input_max);
+        let x_min =
+            bounds.x + (bounds.w / 2.0) * (1.0 + self.peak_min.load(Ordering::Relaxed) / input_max);
         let y_max = bounds.y + bounds.h;
         let y_min = bounds.y;
         peaks.move_to(x_max, y_min);
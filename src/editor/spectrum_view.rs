@@ -0,0 +1,82 @@
+use std::sync::{atomic::Ordering, Arc, Mutex};
+
+use nih_plug::prelude::AtomicF32;
+use nih_plug_vizia::vizia::{
+    prelude::*,
+    vg::{self, Color},
+};
+
+use crate::math::harmonics::Spectra;
+
+/// The quietest partial the spectrum shows, in decibels.
+const FLOOR_DB: f32 = -80.0;
+
+/// Below this input peak the spectrum is shown for a full scale sine instead.
+const MIN_PEAK: f32 = 1e-3;
+
+/// The harmonics the current curve is predicted to produce for a sine as loud as the current input
+/// peak, or a full scale sine while there's no input. The generator analyzes every curve it
+/// builds at a range of levels, and this shows the closest one.
+pub struct SpectrumView {
+    spectra: Arc<Mutex<Spectra>>,
+    peak_max: Arc<AtomicF32>,
+    peak_min: Arc<AtomicF32>,
+}
+
+impl SpectrumView {
+    pub fn new<LSpectra, LPeakMax, LPeakMin>(
+        cx: &mut Context,
+        spectra: LSpectra,
+        peak_max: LPeakMax,
+        peak_min: LPeakMin,
+    ) -> Handle<Self>
+    where
+        LSpectra: Lens<Target = Arc<Mutex<Spectra>>>,
+        LPeakMax: Lens<Target = Arc<AtomicF32>>,
+        LPeakMin: Lens<Target = Arc<AtomicF32>>,
+    {
+        Self {
+            spectra: spectra.get(cx),
+            peak_max: peak_max.get(cx),
+            peak_min: peak_min.get(cx),
+        }
+        .build(cx, |_cx| ())
+    }
+}
+
+impl View for SpectrumView {
+    fn element(&self) -> Option<&'static str> {
+        Some("spectrum_view")
+    }
+
+    fn draw(&self, cx: &mut DrawContext, canvas: &mut Canvas) {
+        let bounds = cx.bounds();
+        let peak = self
+            .peak_max
+            .load(Ordering::Relaxed)
+            .max(-self.peak_min.load(Ordering::Relaxed));
+        let amplitude = if peak > MIN_PEAK { peak } else { 1.0 };
+
+        let spectra = self.spectra.lock().unwrap(); // TODO: Error Handling
+        let spectrum = spectra.get(amplitude);
+        if spectrum.is_empty() {
+            return;
+        }
+
+        let bar_width = bounds.w / spectrum.len() as f32;
+        let mut bars = vg::Path::new();
+        for (i, amplitude) in spectrum.iter().enumerate() {
+            let db = (20.0 * amplitude.log10()).clamp(FLOOR_DB, 0.0);
+            let height = bounds.h * (1.0 - db / FLOOR_DB);
+            if height > 0.0 {
+                bars.rect(
+                    bounds.x + i as f32 * bar_width + 1.0,
+                    bounds.y + bounds.h - height,
+                    (bar_width - 2.0).max(1.0),
+                    height,
+                );
+            }
+        }
+        canvas.fill_path(&bars, &vg::Paint::color(Color::rgb(0, 255, 255)));
+    }
+}
@@ -0,0 +1,278 @@
+use std::{
+    fmt,
+    ops::{Range, RangeInclusive},
+};
+
+use evalexpr::EvalexprError;
+
+/// A location in the expression source. `range` is a byte range for highlighting, `line` and
+/// `column` are one based and meant for displaying to the user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub range: Range<usize>,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    pub fn new(source: &str, range: Range<usize>) -> Self {
+        let before = &source[..range.start.min(source.len())];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let column = before[line_start..].chars().count() + 1;
+        Self {
+            range,
+            line,
+            column,
+        }
+    }
+
+    /// The span of the whole expression, without surrounding whitespace. Used when an error can't
+    /// be attributed to a more specific location.
+    pub fn whole(source: &str) -> Self {
+        let start = source.len() - source.trim_start().len();
+        let end = source.trim_end().len().max(start);
+        Self::new(source, start..end)
+    }
+
+    /// Finds the `nth` occurrence of `identifier` as a whole token in `source`.
+    pub fn identifier(source: &str, identifier: &str, nth: usize) -> Option<Self> {
+        let is_identifier_char = |c: char| c.is_alphanumeric() || c == '_' || c == ':' || c == '.';
+        let mut occurrences = source.match_indices(identifier).filter(|(start, _)| {
+            let end = start + identifier.len();
+            !source[..*start].ends_with(is_identifier_char)
+                && !source[end..].starts_with(is_identifier_char)
+        });
+        occurrences
+            .nth(nth)
+            .map(|(start, _)| Self::new(source, start..start + identifier.len()))
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/// Everything that can go wrong while turning an expression into a shaper table.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShaperError {
+    /// The expression is not syntactically valid.
+    Parse { message: String, span: Span },
+    /// A variable or function that is not defined in the expression context.
+    UnknownIdentifier { identifier: String, span: Span },
+    /// A custom function was called with the wrong amount of arguments.
+    ArgumentCount {
+        function: String,
+        expected: RangeInclusive<usize>,
+        actual: usize,
+        span: Span,
+    },
+    /// A custom function was called with an argument of the wrong type.
+    ArgumentType {
+        function: String,
+        /// Zero based index of the offending argument.
+        argument: usize,
+        expected: &'static str,
+        actual: String,
+        span: Span,
+    },
+    /// The expression uses something the exact engine can't compile. The table can still be
+    /// used in this case.
+    Unsupported { message: String, span: Span },
+    /// Evaluating the expression failed for table entry `index`, which corresponds to the input
+    /// value `x`.
+    Eval {
+        message: String,
+        index: usize,
+        x: f32,
+    },
+    /// The generated table contains `count` NaN or infinite entries, the first of which is
+    /// `value` at table entry `index`.
+    NonFinite {
+        index: usize,
+        x: f32,
+        value: f32,
+        count: usize,
+    },
+    /// A newer request superseded this one before it finished.
+    Cancelled,
+}
+
+impl ShaperError {
+    /// Converts an error returned by `build_operator_tree()` for `source`.
+    pub fn parse(error: EvalexprError, source: &str) -> Self {
+        let span = match error {
+            EvalexprError::UnmatchedLBrace => unmatched_brace(source, true),
+            EvalexprError::UnmatchedRBrace => unmatched_brace(source, false),
+            EvalexprError::UnmatchedDoubleQuote => source
+                .rfind('"')
+                .map(|start| Span::new(source, start..start + 1)),
+            EvalexprError::UnmatchedPartialToken { ref first, .. } => {
+                let token = first.to_string();
+                source
+                    .find(&token)
+                    .map(|start| Span::new(source, start..start + token.len()))
+            }
+            _ => None,
+        }
+        .unwrap_or_else(|| Span::whole(source));
+
+        let message = match error {
+            EvalexprError::UnmatchedLBrace => "Unclosed opening parenthesis".to_owned(),
+            EvalexprError::UnmatchedRBrace => "Closing parenthesis without a match".to_owned(),
+            EvalexprError::UnmatchedDoubleQuote => "Unclosed string literal".to_owned(),
+            EvalexprError::AppendedToLeafNode | EvalexprError::PrecedenceViolation => {
+                "Missing operator between two values".to_owned()
+            }
+            EvalexprError::UnmatchedPartialToken { first, .. } => {
+                format!("Unexpected token '{first}'")
+            }
+            error => error.to_string(),
+        };
+
+        Self::Parse { message, span }
+    }
+
+    /// Converts an error returned while evaluating `source` for table entry `index` at input `x`.
+    pub fn eval(error: EvalexprError, source: &str, index: usize, x: f32) -> Self {
+        match error {
+            EvalexprError::VariableIdentifierNotFound(identifier)
+            | EvalexprError::FunctionIdentifierNotFound(identifier) => Self::UnknownIdentifier {
+                span: Span::identifier(source, &identifier, 0)
+                    .unwrap_or_else(|| Span::whole(source)),
+                identifier,
+            },
+            error => Self::Eval {
+                message: error.to_string(),
+                index,
+                x,
+            },
+        }
+    }
+
+    /// The location in the source this error refers to, if there is one.
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            Self::Parse { span, .. }
+            | Self::UnknownIdentifier { span, .. }
+            | Self::ArgumentCount { span, .. }
+            | Self::ArgumentType { span, .. }
+            | Self::Unsupported { span, .. } => Some(span),
+            Self::Eval { .. } | Self::NonFinite { .. } | Self::Cancelled => None,
+        }
+    }
+
+    /// Replaces the location this error refers to, if there is one, with `f` applied to it.
+    pub fn map_span(mut self, f: impl FnOnce(&Span) -> Span) -> Self {
+        match &mut self {
+            Self::Parse { span, .. }
+            | Self::UnknownIdentifier { span, .. }
+            | Self::ArgumentCount { span, .. }
+            | Self::ArgumentType { span, .. }
+            | Self::Unsupported { span, .. } => *span = f(span),
+            Self::Eval { .. } | Self::NonFinite { .. } | Self::Cancelled => (),
+        }
+        self
+    }
+
+    /// Formats the error together with the offending line of `source` and a marker underneath
+    /// the span, similar to compiler diagnostics.
+    pub fn annotate(&self, source: &str) -> String {
+        let Some(span) = self.span() else {
+            return self.to_string();
+        };
+
+        let line_start = source[..span.range.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[line_start..]
+            .find('\n')
+            .map_or(source.len(), |i| line_start + i);
+        let line = &source[line_start..line_end];
+        let marker_len = source[span.range.start..span.range.end.min(line_end)]
+            .chars()
+            .count()
+            .max(1);
+
+        format!(
+            "{self}\n{line}\n{}{}",
+            " ".repeat(span.column - 1),
+            "^".repeat(marker_len)
+        )
+    }
+}
+
+impl fmt::Display for ShaperError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse { message, span } => write!(f, "Syntax error at {span}: {message}"),
+            Self::UnknownIdentifier { identifier, span } => {
+                write!(f, "Unknown identifier '{identifier}' at {span}")
+            }
+            Self::ArgumentCount {
+                function,
+                expected,
+                actual,
+                span,
+            } => {
+                if expected.start() == expected.end() {
+                    write!(f, "'{function}' at {span} takes {} ", expected.start())?;
+                } else {
+                    write!(
+                        f,
+                        "'{function}' at {span} takes {} to {} ",
+                        expected.start(),
+                        expected.end()
+                    )?;
+                }
+                write!(f, "arguments, but got {actual}")
+            }
+            Self::ArgumentType {
+                function,
+                argument,
+                expected,
+                actual,
+                span,
+            } => write!(
+                f,
+                "Argument {} of '{function}' at {span} must be {expected}, but is {actual}",
+                argument + 1
+            ),
+            Self::Unsupported { message, span } => write!(f, "{message} at {span}"),
+            Self::Eval { message, index, x } => {
+                write!(
+                    f,
+                    "Evaluation failed at x = {x} (table index {index}): {message}"
+                )
+            }
+            Self::NonFinite {
+                index,
+                x,
+                value,
+                count,
+            } => write!(
+                f,
+                "The curve is {value} at x = {x} (table index {index}), {count} entries are not finite"
+            ),
+            Self::Cancelled => write!(f, "Cancelled by a newer request"),
+        }
+    }
+}
+
+impl std::error::Error for ShaperError {}
+
+/// Finds the first parenthesis that doesn't have a partner. Searches for an opening one if
+/// `opening` is set, otherwise for a closing one.
+fn unmatched_brace(source: &str, opening: bool) -> Option<Span> {
+    let mut open = Vec::new();
+    for (i, c) in source.char_indices() {
+        match c {
+            '(' => open.push(i),
+            ')' if open.pop().is_none() && !opening => return Some(Span::new(source, i..i + 1)),
+            _ => (),
+        }
+    }
+    open.first()
+        .filter(|_| opening)
+        .map(|&start| Span::new(source, start..start + 1))
+}
@@ -0,0 +1,257 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use nih_plug::prelude::{nih_log, AtomicF32};
+
+use crate::curve::CurveSettings;
+use crate::error::ShaperError;
+use crate::math::harmonics::{Spectra, NUM_HARMONICS};
+use crate::shaper::Shaper;
+use crate::MathshaperParams;
+
+/// Builds shapers on the background thread and publishes them to the editor and the audio thread.
+/// Every request gets an id, and a build notices when a newer request was made in the meantime
+/// and stops early.
+pub struct Generator {
+    params: Arc<MathshaperParams>,
+    /// The shaper shown in the editor. Kept here so curves built while the editor is closed show
+    /// up once it gets opened.
+    pub display_shaper: Arc<Mutex<Shaper>>,
+    /// The harmonics the displayed shaper produces, analyzed once per build.
+    pub display_spectra: Arc<Mutex<Spectra>>,
+    shaper_input_data: Mutex<triple_buffer::Input<Shaper>>,
+    /// The id of the most recent request.
+    latest: AtomicU64,
+    /// The id of the request made from the audio thread that has not been handled yet, or 0 if
+    /// there is none.
+    audio_request: AtomicU64,
+    /// Settings committed in the editor that haven't been built yet. These only replace the
+    /// persisted settings once they build successfully, so a broken expression never gets saved.
+    candidate: Mutex<Option<CurveSettings>>,
+    /// The host's sample rate, available to expressions as `SR`.
+    sample_rate: AtomicF32,
+    /// The outcome of the last finished build, shown in the editor. Empty if there's nothing to
+    /// report.
+    message: Mutex<String>,
+    /// Called whenever the settings or the message change, so an open editor can refresh.
+    listener: Mutex<Option<Box<dyn FnMut() + Send>>>,
+}
+
+impl Generator {
+    pub fn new(
+        params: Arc<MathshaperParams>,
+        shaper_input_data: triple_buffer::Input<Shaper>,
+    ) -> Self {
+        Self {
+            params,
+            display_shaper: Arc::default(),
+            display_spectra: Arc::default(),
+            shaper_input_data: Mutex::new(shaper_input_data),
+            latest: AtomicU64::new(0),
+            audio_request: AtomicU64::new(0),
+            candidate: Mutex::new(None),
+            message: Mutex::new(String::new()),
+            sample_rate: AtomicF32::new(44100.0),
+            listener: Mutex::new(None),
+        }
+    }
+
+    /// Replaces the function that gets called whenever [`settings()`][Self::settings()] or
+    /// [`message()`][Self::message()] change. This may be called from any thread.
+    pub fn set_listener(&self, listener: Option<Box<dyn FnMut() + Send>>) {
+        *self.listener.lock().unwrap() = listener;
+    }
+
+    fn notify(&self) {
+        if let Some(listener) = &mut *self.listener.lock().unwrap() {
+            listener();
+        }
+    }
+
+    /// Only affects later builds, so this should be followed by a request.
+    pub fn set_sample_rate(&self, sample_rate: f32) {
+        self.sample_rate.store(sample_rate, Ordering::Relaxed);
+    }
+
+    /// Starts a new request, cancelling all older ones, and returns its id. The id needs to be
+    /// passed to [`run()`][Self::run()] from a background task.
+    pub fn request(&self) -> u64 {
+        self.latest.fetch_add(1, Ordering::AcqRel) + 1
+    }
+
+    /// Like [`request()`][Self::request()], but returns `None` while an earlier request from the
+    /// audio thread is still being handled. This keeps automation from flooding the task queue
+    /// and from cancelling every build before it can finish.
+    pub fn request_from_audio_thread(&self) -> Option<u64> {
+        if self.audio_request.load(Ordering::Acquire) != 0 {
+            return None;
+        }
+        let id = self.request();
+        self.audio_request.store(id, Ordering::Release);
+        Some(id)
+    }
+
+    /// Requests a build with new settings from the editor.
+    pub fn commit(&self, curve: CurveSettings) -> u64 {
+        *self.candidate.lock().unwrap() = Some(curve);
+        let id = self.request();
+        self.notify();
+        id
+    }
+
+    /// The settings the next build will use. This includes changes that are still being built.
+    pub fn settings(&self) -> CurveSettings {
+        match &*self.candidate.lock().unwrap() {
+            Some(candidate) => candidate.clone(),
+            None => self.params.curve.read().unwrap().clone(),
+        }
+    }
+
+    pub fn message(&self) -> String {
+        self.message.lock().unwrap().clone()
+    }
+
+    pub fn set_message(&self, message: String) {
+        *self.message.lock().unwrap() = message;
+        self.notify();
+    }
+
+    /// Builds and publishes the shaper for request `id`, unless a newer request has been made.
+    pub fn run(&self, id: u64) {
+        let message = self.build(id);
+        // Other requests finishing doesn't mean the audio thread's one has been handled
+        let _ = self
+            .audio_request
+            .compare_exchange(id, 0, Ordering::AcqRel, Ordering::Relaxed);
+        if let Some(message) = message {
+            self.set_message(message);
+        }
+    }
+
+    /// Returns the message to show in the editor, or `None` if the request was cancelled.
+    fn build(&self, id: u64) -> Option<String> {
+        let is_cancelled = || self.latest.load(Ordering::Acquire) != id;
+        if is_cancelled() {
+            return None;
+        }
+
+        let candidate = self.candidate.lock().unwrap().clone();
+        let curve = match &candidate {
+            Some(candidate) => candidate.clone(),
+            None => self.params.curve.read().unwrap().clone(),
+        };
+
+        let sample_rate = self.sample_rate.load(Ordering::Relaxed);
+        let shaper: Shaper = match curve.build(&self.params.macros(), sample_rate, &is_cancelled) {
+            Ok(shaper) => shaper,
+            Err(ShaperError::Cancelled) => return None,
+            Err(err) => {
+                nih_log!("Failed to build the curve '{}': {err}", curve.expression);
+                // A broken candidate is dropped so later builds go back to the persisted
+                // settings, unless the editor committed something else in the meantime
+                let mut current = self.candidate.lock().unwrap();
+                if *current == candidate {
+                    *current = None;
+                }
+                return Some(err.annotate(&curve.expression));
+            }
+        };
+
+        // The curve itself is fine in this case, this is only a heads-up
+        let message = match shaper.program_error() {
+            Some(err) => format!(
+                "Exact mode uses the table for this curve: {}",
+                err.annotate(&curve.expression)
+            ),
+            None => String::new(),
+        };
+        self.shaper_input_data.lock().unwrap().write(shaper.clone());
+        let spectra = Spectra::analyze(|x| shaper.process(x), shaper.input_max(), NUM_HARMONICS);
+        *self.display_spectra.lock().unwrap() = spectra;
+        *self.display_shaper.lock().unwrap() = shaper;
+
+        let mut current = self.candidate.lock().unwrap();
+        if candidate.is_some() && *current == candidate {
+            *current = None;
+            *self.params.curve.write().unwrap() = curve;
+        }
+        Some(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use triple_buffer::TripleBuffer;
+
+    use super::*;
+
+    fn generator() -> (Generator, triple_buffer::Output<Shaper>) {
+        let (input, output) = TripleBuffer::default().split();
+        let generator = Generator::new(Arc::new(MathshaperParams::default()), input);
+        (generator, output)
+    }
+
+    fn curve(expression: &str) -> CurveSettings {
+        CurveSettings {
+            expression: expression.to_owned(),
+            ..CurveSettings::default()
+        }
+    }
+
+    #[test]
+    fn newer_requests_cancel_older_ones() {
+        let (generator, output) = generator();
+        let stale = generator.commit(curve("x^2"));
+        let latest = generator.commit(curve("x^3"));
+
+        generator.run(stale);
+        assert!(!output.updated());
+        assert_eq!(generator.settings().expression, "x^3");
+
+        generator.run(latest);
+        assert!(output.updated());
+        assert_eq!(generator.message(), "");
+        assert_eq!(generator.params.curve.read().unwrap().expression, "x^3");
+        assert_eq!(
+            generator.display_spectra.lock().unwrap().get(1.0).len(),
+            NUM_HARMONICS
+        );
+    }
+
+    #[test]
+    fn broken_curves_are_not_saved() {
+        let (generator, output) = generator();
+        generator.run(generator.commit(curve("x +")));
+        assert!(!output.updated());
+        assert!(!generator.message().is_empty());
+        assert_eq!(generator.settings(), CurveSettings::default());
+    }
+
+    #[test]
+    fn listener_sees_rolled_back_settings() {
+        let (generator, _output) = generator();
+        let notified = Arc::new(AtomicU64::new(0));
+        let counter = notified.clone();
+        generator.set_listener(Some(Box::new(move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+        })));
+
+        let id = generator.commit(curve("x +"));
+        assert_eq!(notified.load(Ordering::Relaxed), 1);
+        generator.run(id);
+        assert_eq!(notified.load(Ordering::Relaxed), 2);
+        assert_eq!(generator.settings(), CurveSettings::default());
+    }
+
+    #[test]
+    fn audio_thread_requests_wait_for_the_previous_one() {
+        let (generator, _output) = generator();
+        let id = generator.request_from_audio_thread().unwrap();
+        assert_eq!(generator.request_from_audio_thread(), None);
+        // A build started from the editor in the meantime doesn't count
+        generator.run(generator.request());
+        assert_eq!(generator.request_from_audio_thread(), None);
+        generator.run(id);
+        assert!(generator.request_from_audio_thread().is_some());
+    }
+}
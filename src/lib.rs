@@ -1,30 +1,75 @@
+mod adaa;
+mod curve;
+mod delay;
 mod editor;
+mod error;
+mod generator;
 mod math;
+mod oversampling;
+mod prompt_file;
 mod shaper;
 
+use adaa::{Adaa, AdaaOrder};
 use core::f32;
+use curve::CurveSettings;
+use delay::DelayLine;
+use generator::Generator;
 use nih_plug::prelude::*;
 use nih_plug_vizia::ViziaState;
-use shaper::Shaper as GenericShaper;
-use std::sync::{Arc, Mutex};
+use oversampling::{Oversampling, OversamplingFactor};
+use prompt_file::{PromptFile, PromptFileSettings};
+use shaper::{Macros, Shaper};
+use std::sync::{Arc, RwLock};
 use triple_buffer::TripleBuffer;
-use valib::oversample::Oversample;
 // This is a shortened version of the gain example with most comments removed, check out
 // https://github.com/robbert-vdh/nih-plug/blob/master/plugins/examples/gain/src/lib.rs to get
 // started
 
-type Shaper = GenericShaper<512>; // TODO: Figure out size
-
 const MAX_BLOCK_SIZE: usize = 512;
-const OVERSAMPLE_MAX: usize = 16;
 
 pub struct Mathshaper {
     params: Arc<MathshaperParams>,
     peak_max: Arc<AtomicF32>,
     peak_min: Arc<AtomicF32>,
-    shaper_input_data: Arc<Mutex<triple_buffer::Input<Shaper>>>,
+    generator: Arc<Generator>,
+    prompt_file: Arc<PromptFile>,
     shaper_output_data: triple_buffer::Output<Shaper>,
-    resamplers: Box<[Oversample<f32>]>,
+    /// The curve the DSP is fading towards, copied out of `shaper_output_data`.
+    current_shaper: Shaper,
+    /// The curve that was playing before `current_shaper` was published.
+    previous_shaper: Shaper,
+    /// How far the crossfade from `previous_shaper` to `current_shaper` has progressed, from 0 to
+    /// 1.
+    fade: f32,
+    sample_rate: f32,
+    oversampling: Oversampling,
+    /// The latency last reported to the host, in samples.
+    latency: u32,
+    /// The ADAA input history, one per channel.
+    adaa: Box<[Adaa]>,
+    /// Delays the dry signal by the latency, including the fractions of a sample that can't be
+    /// reported to the host, one per channel.
+    dry_delays: Box<[DelayLine]>,
+    /// The macro values the current table was requested with.
+    macros: Macros,
+}
+
+/// Work that should not be done on the audio thread.
+pub enum Task {
+    /// Build the shaper for a request made with [`Generator::request()`]. Does nothing if a newer
+    /// request has been made since.
+    Generate(u64),
+}
+
+/// How the shaping function is evaluated on the audio thread.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShaperMode {
+    /// Linearly interpolate the lookup table.
+    #[name = "Table"]
+    Table,
+    /// Evaluate the compiled expression for every oversampled sample.
+    #[name = "Exact"]
+    Exact,
 }
 
 #[derive(Params)]
@@ -35,24 +80,62 @@ struct MathshaperParams {
     /// gain parameter is stored as linear gain while the values are displayed in decibels.
     #[persist = "editor-state"]
     editor_state: Arc<ViziaState>,
+    /// The expression and settings the shaper table is generated from. The table itself is not
+    /// stored, it gets rebuilt from these in `initialize()`.
+    #[persist = "curve"]
+    pub curve: Arc<RwLock<CurveSettings>>,
+    #[persist = "prompt-file"]
+    pub prompt_file: Arc<RwLock<PromptFileSettings>>,
     #[id = "pre_gain"]
     pub pre_gain: FloatParam,
     #[id = "post_gain"]
     pub post_gain: FloatParam,
+    /// The balance between the input and the shaped signal, applied after `post_gain`.
+    #[id = "mix"]
+    pub mix: FloatParam,
     #[id = "decay"]
     pub decay: FloatParam,
+    /// How long it takes to fade over to a newly published curve, in milliseconds.
+    #[id = "crossfade"]
+    pub crossfade: FloatParam,
+    #[id = "oversampling"]
+    pub oversampling: EnumParam<OversamplingFactor>,
+    #[id = "adaa"]
+    pub adaa: EnumParam<AdaaOrder>,
+    #[id = "mode"]
+    pub mode: EnumParam<ShaperMode>,
+    /// Macros are available as the variables `a` to `d` in expressions.
+    #[id = "macro_a"]
+    pub macro_a: FloatParam,
+    #[id = "macro_b"]
+    pub macro_b: FloatParam,
+    #[id = "macro_c"]
+    pub macro_c: FloatParam,
+    #[id = "macro_d"]
+    pub macro_d: FloatParam,
 }
 
 impl Default for Mathshaper {
     fn default() -> Self {
         let (shaper_in, shaper_out) = TripleBuffer::default().split();
+        let params = Arc::new(MathshaperParams::default());
+        let generator = Arc::new(Generator::new(params.clone(), shaper_in));
         Self {
-            params: Arc::new(MathshaperParams::default()),
+            generator: generator.clone(),
+            prompt_file: Arc::new(PromptFile::new(params.prompt_file.clone(), generator)),
+            params,
             peak_max: Arc::default(),
             peak_min: Arc::default(),
-            shaper_input_data: Arc::new(Mutex::new(shaper_in)),
             shaper_output_data: shaper_out,
-            resamplers: vec![].into_boxed_slice(),
+            current_shaper: Shaper::preallocated(),
+            previous_shaper: Shaper::preallocated(),
+            fade: 1.0,
+            sample_rate: 44100.0,
+            oversampling: Oversampling::default(),
+            latency: 0,
+            adaa: Box::default(),
+            dry_delays: Box::default(),
+            macros: Macros::default(),
         }
     }
 }
@@ -64,6 +147,8 @@ impl Default for MathshaperParams {
             // to treat these kinds of parameters as if we were dealing with decibels. Storing this
             // as decibels is easier to work with, but requires a conversion for every sample.
             editor_state: editor::default_state(),
+            curve: Arc::new(RwLock::new(CurveSettings::default())),
+            prompt_file: Arc::new(RwLock::new(PromptFileSettings::default())),
             pre_gain: FloatParam::new(
                 "Pre Gain",
                 util::db_to_gain(0.0),
@@ -104,11 +189,53 @@ impl Default for MathshaperParams {
             // `.with_step_size(0.1)` function to get internal rounding.
             .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
             .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+            mix: FloatParam::new("Mix", 1.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(20.0))
+                .with_unit(" %")
+                .with_value_to_string(formatters::v2s_f32_percentage(0))
+                .with_string_to_value(formatters::s2v_f32_percentage()),
             decay: FloatParam::new("decay", 0.4, FloatRange::Linear { min: 0.0, max: 3.0 }),
+            crossfade: FloatParam::new(
+                "Crossfade",
+                20.0,
+                FloatRange::Skewed {
+                    min: 0.0,
+                    max: 1000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            oversampling: EnumParam::new("Oversampling", OversamplingFactor::X16),
+            adaa: EnumParam::new("ADAA", AdaaOrder::Off),
+            mode: EnumParam::new("Mode", ShaperMode::Table),
+            macro_a: FloatParam::new("Macro A", 0.5, FloatRange::Linear { min: 0.0, max: 1.0 }),
+            macro_b: FloatParam::new("Macro B", 0.5, FloatRange::Linear { min: 0.0, max: 1.0 }),
+            macro_c: FloatParam::new("Macro C", 0.5, FloatRange::Linear { min: 0.0, max: 1.0 }),
+            macro_d: FloatParam::new("Macro D", 0.5, FloatRange::Linear { min: 0.0, max: 1.0 }),
         }
     }
 }
 
+impl MathshaperParams {
+    pub fn macros(&self) -> Macros {
+        [
+            self.macro_a.value(),
+            self.macro_b.value(),
+            self.macro_c.value(),
+            self.macro_d.value(),
+        ]
+    }
+}
+
+impl Mathshaper {
+    /// The latency of the current oversampling and ADAA settings, in samples.
+    fn latency(&self) -> u32 {
+        let factor = self.params.oversampling.value();
+        self.oversampling.latency(factor) + self.params.adaa.value().latency(factor.factor())
+    }
+}
+
 impl Plugin for Mathshaper {
     const NAME: &'static str = "Mathshaper";
     const VENDOR: &'static str = "Finn Heintzmann";
@@ -144,36 +271,60 @@ impl Plugin for Mathshaper {
     // More advanced plugins can use this to run expensive background tasks. See the field's
     // documentation for more information. `()` means that the plugin does not have any background
     // tasks.
-    type BackgroundTask = ();
+    type BackgroundTask = Task;
 
     fn params(&self) -> Arc<dyn Params> {
         self.params.clone()
     }
 
-    fn editor(&mut self, _async_executor: AsyncExecutor<Self>) -> Option<Box<dyn Editor>> {
+    fn task_executor(&mut self) -> TaskExecutor<Self> {
+        let generator = self.generator.clone();
+        Box::new(move |task| match task {
+            Task::Generate(id) => generator.run(id),
+        })
+    }
+
+    fn editor(&mut self, async_executor: AsyncExecutor<Self>) -> Option<Box<dyn Editor>> {
         editor::create(
             self.params.clone(),
             self.params.editor_state.clone(),
             self.peak_max.clone(),
             self.peak_min.clone(),
-            self.shaper_input_data.clone(),
+            self.generator.clone(),
+            self.prompt_file.clone(),
+            async_executor,
         )
     }
 
     fn initialize(
         &mut self,
         audio_io_layout: &AudioIOLayout,
-        _buffer_config: &BufferConfig,
-        _context: &mut impl InitContext<Self>,
+        buffer_config: &BufferConfig,
+        context: &mut impl InitContext<Self>,
     ) -> bool {
-        println!("input channels: {:?}", audio_io_layout.main_output_channels);
         let input_channels = audio_io_layout
             .main_input_channels
             .unwrap_or(unsafe { NonZeroU32::new_unchecked(1) })
             .get() as usize;
-        let resamplers =
-            vec![Oversample::<f32>::new(OVERSAMPLE_MAX, MAX_BLOCK_SIZE); input_channels];
-        self.resamplers = resamplers.into_boxed_slice();
+        self.oversampling = Oversampling::new(input_channels, MAX_BLOCK_SIZE);
+        self.adaa = vec![Adaa::default(); input_channels].into_boxed_slice();
+        self.dry_delays = (0..input_channels)
+            .map(|_| {
+                DelayLine::new((self.oversampling.max_latency() + AdaaOrder::MAX_LATENCY) as usize)
+            })
+            .collect();
+        // The latency is checked again at the start of every block in case the settings change
+        self.latency = self.latency();
+        context.set_latency_samples(self.latency);
+        self.sample_rate = buffer_config.sample_rate;
+
+        // This is also called after the state has been restored, so this is where the persisted
+        // curve gets turned back into a table for the DSP side and the prompt file watcher gets
+        // started again
+        self.macros = self.params.macros();
+        self.generator.set_sample_rate(self.sample_rate);
+        context.execute(Task::Generate(self.generator.request()));
+        self.prompt_file.sync();
         // Resize buffers and perform other potentially expensive initialization operations here.
         // The `reset()` function is always called right after this function. You can remove this
         // function if you do not need it.
@@ -183,39 +334,117 @@ impl Plugin for Mathshaper {
     fn reset(&mut self) {
         // Reset buffers and envelopes here. This can be called from the audio thread and may not
         // allocate. You can remove this function if you do not need it.
+        for adaa in self.adaa.iter_mut() {
+            adaa.reset();
+        }
+        for delay in self.dry_delays.iter_mut() {
+            delay.reset();
+        }
+        self.oversampling.reset();
     }
 
     fn process(
         &mut self,
         buffer: &mut Buffer,
         _aux: &mut AuxiliaryBuffers,
-        _context: &mut impl ProcessContext<Self>,
+        context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
+        let macros = self.params.macros();
+        if macros != self.macros {
+            // If a build is still running this is retried on the next block
+            if let Some(id) = self.generator.request_from_audio_thread() {
+                self.macros = macros;
+                context.execute_background(Task::Generate(id));
+            }
+        }
+
         let mut new_peak_max = f32::MIN;
         let mut new_peak_min = f32::MAX;
 
-        let shaper_data = self.shaper_output_data.read();
+        // A running fade finishes first, restarting it would jump back to the curve it was fading
+        // away from. Curves published in the meantime are skipped except for the latest one.
+        if self.fade >= 1.0 && self.shaper_output_data.updated() {
+            self.previous_shaper.copy_from(&self.current_shaper);
+            self.current_shaper
+                .copy_from(self.shaper_output_data.read());
+            self.fade = 0.0;
+        }
+        let oversampling_factor = self.params.oversampling.value();
+        let latency = self.latency();
+        if latency != self.latency {
+            self.latency = latency;
+            context.set_latency_samples(latency);
+        }
+
+        let fade_samples = self.params.crossfade.value()
+            * 0.001
+            * self.sample_rate
+            * oversampling_factor.factor() as f32;
+        let fade_step = if fade_samples > 1.0 {
+            fade_samples.recip()
+        } else {
+            1.0
+        };
+        let exact = self.params.mode.value() == ShaperMode::Exact;
+        let adaa_order = self.params.adaa.value();
+        let dry_delay = self.oversampling.latency(oversampling_factor) as f32
+            + adaa_order.delay(oversampling_factor.factor());
+
+        let mut mix = [0.0; MAX_BLOCK_SIZE];
+        let mut dry = [0.0; MAX_BLOCK_SIZE];
 
         for (_, block) in buffer.iter_blocks(MAX_BLOCK_SIZE) {
+            // Every channel fades along the same ramp
+            let block_fade = self.fade;
+            let block_len = block.samples();
+            self.params.mix.smoothed.next_block(&mut mix, block_len);
             for (channel, io_buffer) in block.into_iter().enumerate() {
-                if channel >= self.resamplers.len() {
+                if channel >= self.oversampling.channels() {
                     nih_log!("Channel index out of bounds");
                     break;
                 }
 
-                let mut oversampled_block = self.resamplers[channel].oversample(io_buffer);
+                dry[..block_len].copy_from_slice(io_buffer);
 
                 let pre_gain = self.params.pre_gain.smoothed.next();
                 let post_gain = self.params.post_gain.smoothed.next();
 
-                for sample in oversampled_block.iter_mut() {
-                    *sample = *sample * pre_gain;
-                    new_peak_max = new_peak_max.max(*sample);
-                    new_peak_min = new_peak_min.min(*sample);
-                    *sample = shaper_data.process(*sample) * post_gain;
+                let adaa = &mut self.adaa[channel];
+                let mut fade = block_fade;
+                self.oversampling
+                    .process(oversampling_factor, channel, io_buffer, |samples| {
+                        for sample in samples.iter_mut() {
+                            *sample = *sample * pre_gain;
+                            new_peak_max = new_peak_max.max(*sample);
+                            new_peak_min = new_peak_min.min(*sample);
+                            adaa.push(*sample);
+                            let adaa = &*adaa;
+                            let shape = |shaper: &Shaper, x: f32| {
+                                if adaa_order != AdaaOrder::Off {
+                                    adaa.process(adaa_order, shaper)
+                                } else if exact {
+                                    shaper.process_exact(x, &macros)
+                                } else {
+                                    shaper.process(x)
+                                }
+                            };
+
+                            let mut shaped = shape(&self.current_shaper, *sample);
+                            if fade < 1.0 {
+                                fade = (fade + fade_step).min(1.0);
+                                let previous = shape(&self.previous_shaper, *sample);
+                                shaped = previous + (shaped - previous) * fade;
+                            }
+                            *sample = shaped * post_gain;
+                        }
+                    });
+                self.fade = fade;
+
+                let delay = &mut self.dry_delays[channel];
+                for ((sample, dry), mix) in io_buffer.iter_mut().zip(dry).zip(mix) {
+                    let dry = delay.process_fractional(dry, dry_delay);
+                    *sample = dry + (*sample - dry) * mix;
                 }
-
-                oversampled_block.finish(io_buffer);
             }
         }
 
@@ -238,7 +467,7 @@ impl Plugin for Mathshaper {
             .store(peak_max, std::sync::atomic::Ordering::Relaxed);
         self.peak_min
             .store(peak_min, std::sync::atomic::Ordering::Relaxed);
-        
+
         ProcessStatus::Normal
     }
 }
@@ -1 +1,38 @@
+use evalexpr::EvalexprError;
+
+pub mod bernstein;
 pub mod chebychev;
+pub mod harmonics;
+pub mod hermite;
+pub mod laguerre;
+pub mod legendre;
+pub mod shapes;
+
+/// The highest order the polynomial families accept. Higher orders are far beyond what a shaper
+/// table can resolve, and most of the polynomials overflow quickly outside of [-1, 1].
+pub const MAX_ORDER: f64 = 1024.0;
+
+/// Whether `order` is a valid polynomial order. Unlike [`check_order()`] this never allocates, so
+/// the exact engine can use it on the audio thread.
+pub fn is_valid_order(order: f64) -> bool {
+    (0.0..=MAX_ORDER).contains(&order)
+}
+
+/// Checks that `order` is a valid polynomial order, `family` names the polynomials in the error.
+fn check_order(family: &str, order: f64) -> Result<(), EvalexprError> {
+    if is_valid_order(order) {
+        Ok(())
+    } else if !order.is_finite() {
+        Err(EvalexprError::CustomMessage(format!(
+            "{family} order must be finite"
+        )))
+    } else if order < 0.0 {
+        Err(EvalexprError::CustomMessage(format!(
+            "{family} order can't be negative"
+        )))
+    } else {
+        Err(EvalexprError::CustomMessage(format!(
+            "{family} order can't be above {MAX_ORDER}"
+        )))
+    }
+}
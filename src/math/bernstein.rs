@@ -0,0 +1,92 @@
+use evalexpr::EvalexprError;
+
+/// The highest order [`bernstein()`] accepts. The triangle it evaluates is kept on the stack.
+pub const MAX_BERNSTEIN_ORDER: usize = 64;
+
+/// The Bernstein basis polynomial, see [`basis()`].
+pub(crate) fn bernstein(value: &f64, index: &i64, order: &i64) -> Result<f64, EvalexprError> {
+    if *order < 0 || *index < 0 {
+        return Err(EvalexprError::CustomMessage(
+            "Bernstein index and order can't be negative".to_owned(),
+        ));
+    }
+    if *order as usize > MAX_BERNSTEIN_ORDER {
+        return Err(EvalexprError::CustomMessage(format!(
+            "Bernstein order can't be above {MAX_BERNSTEIN_ORDER}"
+        )));
+    }
+    Ok(basis(*value, *index as usize, *order as usize))
+}
+
+/// The Bernstein basis polynomial `(order choose index) x^index (1 - x)^(order - index)`,
+/// evaluated with de Casteljau's algorithm, which only ever forms convex combinations for x in
+/// [0, 1]. `order` can't be above [`MAX_BERNSTEIN_ORDER`].
+pub(crate) fn basis(x: f64, index: usize, order: usize) -> f64 {
+    if index > order {
+        return 0.0;
+    }
+
+    // The control values of the basis polynomial are all zero except for the one at `index`
+    let mut points = [0.0; MAX_BERNSTEIN_ORDER + 1];
+    points[index] = 1.0;
+    de_casteljau(&mut points[..=order], x)
+}
+
+/// The cubic Bézier curve with control values `points`, at `t`. Useful for smooth knees between
+/// two linear segments.
+pub(crate) fn bezier(t: f64, points: [f64; 4]) -> f64 {
+    let mut points = points;
+    de_casteljau(&mut points, t)
+}
+
+/// Evaluates the polynomial with the given Bernstein coefficients at `t`, overwriting them.
+fn de_casteljau(points: &mut [f64], t: f64) -> f64 {
+    for level in (1..points.len()).rev() {
+        for i in 0..level {
+            points[i] = (1.0 - t) * points[i] + t * points[i + 1];
+        }
+    }
+    points[0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shaper::tests::assert_close;
+
+    #[test]
+    fn cubic_basis() {
+        for x in [0.0, 0.25, 0.6, 1.0] {
+            let s = 1.0 - x;
+            let expected = [s * s * s, 3.0 * x * s * s, 3.0 * x * x * s, x * x * x];
+            for (index, expected) in expected.into_iter().enumerate() {
+                assert_close(basis(x, index, 3), expected, 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn partition_of_unity() {
+        for x in [0.1, 0.5, 0.9] {
+            let sum: f64 = (0..=10).map(|index| basis(x, index, 10)).sum();
+            assert_close(sum, 1.0, 1e-12);
+        }
+        assert_eq!(basis(0.5, 4, 3), 0.0);
+    }
+
+    #[test]
+    fn bezier_curves() {
+        assert_eq!(bezier(0.0, [0.1, 0.5, 0.7, 0.9]), 0.1);
+        assert_eq!(bezier(1.0, [0.1, 0.5, 0.7, 0.9]), 0.9);
+        // Evenly spaced control values give a line
+        assert_close(bezier(0.3, [0.0, 1.0, 2.0, 3.0]), 0.9, 1e-12);
+    }
+
+    #[test]
+    fn invalid_orders() {
+        assert!(bernstein(&0.5, &1, &-1).is_err());
+        assert!(bernstein(&0.5, &-1, &3).is_err());
+        assert!(bernstein(&0.5, &1, &(MAX_BERNSTEIN_ORDER as i64 + 1)).is_err());
+        assert_eq!(bernstein(&0.5, &5, &3), Ok(0.0));
+    }
+}
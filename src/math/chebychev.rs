@@ -1,12 +1,109 @@
 use evalexpr::EvalexprError;
 
-pub(crate) fn chebychev(value: &f64, order: &i64) -> Result<f64, EvalexprError> {
-    match order {
-        n if *n < 0 => Err(EvalexprError::CustomMessage(
-            "Chebychev order can't be negative".to_owned(),
-        )),
-        0 => Ok(1.0),
-        1 => Ok(*value),
-        n => Ok(2.0 * value * chebychev(value, &(n - 1))? - chebychev(value, &(n - 2))?),
+use super::check_order;
+
+/// The Chebyshev polynomial of the first kind, see [`first_kind()`].
+pub(crate) fn chebychev(value: &f64, order: &f64) -> Result<f64, EvalexprError> {
+    check_order("Chebychev", *order)?;
+    Ok(first_kind(*value, *order))
+}
+
+/// The Chebyshev polynomial of the second kind, only defined for whole orders.
+pub(crate) fn chebychev_second_kind(value: &f64, order: &i64) -> Result<f64, EvalexprError> {
+    check_order("Chebychev", *order as f64)?;
+    Ok(second_kind(*value, *order as usize))
+}
+
+/// The Chebyshev polynomial of the first kind for an already checked order. Fractional orders use
+/// `cos(n acos x)`, which is continued with `cosh` outside of [-1, 1]. Below -1 that is the real
+/// part of the complex continuation, which agrees with the polynomial for whole orders.
+pub(crate) fn first_kind(x: f64, order: f64) -> f64 {
+    if order.fract() != 0.0 {
+        return if x.abs() <= 1.0 {
+            (order * x.acos()).cos()
+        } else if x > 1.0 {
+            (order * x.acosh()).cosh()
+        } else {
+            (order * std::f64::consts::PI).cos() * (order * (-x).acosh()).cosh()
+        };
+    }
+
+    recurrence(x, 1.0, x, order as usize)
+}
+
+/// The Chebyshev polynomial of the second kind for an already checked order.
+pub(crate) fn second_kind(x: f64, order: usize) -> f64 {
+    recurrence(x, 1.0, 2.0 * x, order)
+}
+
+/// Iterates the recurrence P(n) = 2x P(n - 1) - P(n - 2) shared by both kinds, starting from P(0)
+/// and P(1). Recursing instead would take exponential time.
+fn recurrence(x: f64, first: f64, second: f64, order: usize) -> f64 {
+    if order == 0 {
+        return first;
+    }
+
+    let (mut previous, mut current) = (first, second);
+    for _ in 1..order {
+        (previous, current) = (current, 2.0 * x * current - previous);
+    }
+    current
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::MAX_ORDER;
+    use crate::shaper::tests::assert_close;
+
+    #[test]
+    fn first_kind_polynomials() {
+        for x in [-1.5, -1.0, -0.3, 0.0, 0.7, 1.0, 2.0] {
+            assert_close(first_kind(x, 0.0), 1.0, 1e-9);
+            assert_close(first_kind(x, 1.0), x, 1e-9);
+            assert_close(first_kind(x, 2.0), 2.0 * x * x - 1.0, 1e-9);
+            assert_close(first_kind(x, 3.0), 4.0 * x * x * x - 3.0 * x, 1e-9);
+        }
+    }
+
+    #[test]
+    fn second_kind_polynomials() {
+        for x in [-1.5, -1.0, -0.3, 0.0, 0.7, 1.0, 2.0] {
+            assert_close(second_kind(x, 0), 1.0, 1e-9);
+            assert_close(second_kind(x, 1), 2.0 * x, 1e-9);
+            assert_close(second_kind(x, 2), 4.0 * x * x - 1.0, 1e-9);
+            assert_close(second_kind(x, 3), 8.0 * x * x * x - 4.0 * x, 1e-9);
+        }
+    }
+
+    #[test]
+    fn first_kind_turns_cosines_into_harmonics() {
+        for t in [0.1, 0.8, 2.5] {
+            assert_close(first_kind(f64::cos(t), 5.0), f64::cos(5.0 * t), 1e-9);
+            assert_close(first_kind(f64::cos(t), 2.5), f64::cos(2.5 * t), 1e-9);
+        }
+    }
+
+    #[test]
+    fn fractional_orders_continue_smoothly() {
+        // The continuation outside of [-1, 1] meets the polynomial at the edges
+        for order in [0.5, 1.5, 2.25] {
+            assert_close(first_kind(1.0 + 1e-12, order), first_kind(1.0, order), 1e-9);
+            assert_close(
+                first_kind(-1.0 - 1e-12, order),
+                first_kind(-1.0, order),
+                1e-9,
+            );
+        }
+        assert_close(first_kind(3.0, 1.5), (1.5 * 3.0f64.acosh()).cosh(), 1e-9);
+    }
+
+    #[test]
+    fn invalid_orders() {
+        assert!(chebychev(&0.5, &-1.0).is_err());
+        assert!(chebychev(&0.5, &f64::NAN).is_err());
+        assert!(chebychev(&0.5, &(MAX_ORDER + 1.0)).is_err());
+        assert!(chebychev(&0.5, &MAX_ORDER).is_ok());
+        assert!(chebychev_second_kind(&0.5, &-2).is_err());
     }
 }
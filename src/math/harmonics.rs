@@ -0,0 +1,172 @@
+/// How many harmonics the harmonic designer offers.
+pub const NUM_HARMONICS: usize = 32;
+
+/// Amplitudes below this are left out of the generated expression.
+const SILENT: f32 = 1e-4;
+
+/// The default design, just the fundamental.
+pub fn default_amplitudes() -> Vec<f32> {
+    let mut amplitudes = vec![0.0; NUM_HARMONICS];
+    amplitudes[0] = 1.0;
+    amplitudes
+}
+
+/// Builds the Chebyshev series for the given harmonic amplitudes, starting at the fundamental.
+/// Since `Cheb(cos(t), n) = cos(n * t)`, a full scale sine shaped by this contains exactly these
+/// partials. Negative amplitudes flip the partial's phase.
+pub fn expression(amplitudes: &[f32]) -> String {
+    let mut expression = String::new();
+    for (index, &amplitude) in amplitudes.iter().enumerate() {
+        if amplitude.abs() < SILENT {
+            continue;
+        }
+
+        let sign = if amplitude < 0.0 { "-" } else { "+" };
+        if expression.is_empty() {
+            if amplitude < 0.0 {
+                expression.push('-');
+            }
+        } else {
+            expression.push_str(&format!(" {sign} "));
+        }
+        expression.push_str(&format!("{} * Cheb(x, {})", amplitude.abs(), index + 1));
+    }
+
+    if expression.is_empty() {
+        "0".to_owned()
+    } else {
+        expression
+    }
+}
+
+/// The number of Chebyshev nodes [`analyze()`] samples the curve at.
+const ANALYSIS_NODES: usize = 256;
+
+/// The reverse of [`expression()`]. Decomposes `curve` into a Chebyshev series with a DCT over
+/// Chebyshev nodes, which gives the amplitudes of the first `harmonics` partials a sine with the
+/// given amplitude produces when shaped by `curve`, starting at the fundamental.
+pub fn analyze(curve: impl Fn(f32) -> f32, amplitude: f32, harmonics: usize) -> Vec<f32> {
+    let nodes: Vec<(f64, f64)> = (0..ANALYSIS_NODES)
+        .map(|k| {
+            let theta = std::f64::consts::PI * (k as f64 + 0.5) / ANALYSIS_NODES as f64;
+            let value = curve(amplitude * theta.cos() as f32) as f64;
+            (theta, if value.is_finite() { value } else { 0.0 })
+        })
+        .collect();
+
+    (1..=harmonics)
+        .map(|n| {
+            let sum: f64 = nodes
+                .iter()
+                .map(|(theta, value)| value * (n as f64 * theta).cos())
+                .sum();
+            (2.0 * sum / ANALYSIS_NODES as f64).abs() as f32
+        })
+        .collect()
+}
+
+/// How far apart the input levels [`Spectra`] analyzes the curve at are, in decibels.
+const LEVEL_STEP_DB: f32 = 3.0;
+
+/// How many input levels [`Spectra`] covers, going down from the curve's input range.
+const LEVELS: usize = 31;
+
+/// A curve's [`analyze()`] results for sines at a range of input levels, so the editor doesn't
+/// need to analyze the curve every time it redraws.
+#[derive(Debug, Clone, Default)]
+pub struct Spectra {
+    max_amplitude: f32,
+    /// One spectrum per level, starting at `max_amplitude`.
+    levels: Vec<Vec<f32>>,
+}
+
+impl Spectra {
+    /// Analyzes `curve` for amplitudes from `max_amplitude` down in steps of
+    /// [`LEVEL_STEP_DB`].
+    pub fn analyze(curve: impl Fn(f32) -> f32, max_amplitude: f32, harmonics: usize) -> Self {
+        let levels = (0..LEVELS)
+            .map(|level| {
+                let gain = 10f32.powf(-(level as f32 * LEVEL_STEP_DB) / 20.0);
+                analyze(&curve, max_amplitude * gain, harmonics)
+            })
+            .collect();
+        Self {
+            max_amplitude,
+            levels,
+        }
+    }
+
+    /// The spectrum for the analyzed level closest to `amplitude`. This is empty if nothing has
+    /// been analyzed yet.
+    pub fn get(&self, amplitude: f32) -> &[f32] {
+        if self.levels.is_empty() {
+            return &[];
+        }
+        let below_max_db = 20.0 * (self.max_amplitude / amplitude).log10();
+        let level = (below_max_db / LEVEL_STEP_DB).round();
+        &self.levels[level.clamp(0.0, (self.levels.len() - 1) as f32) as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::chebychev::first_kind;
+
+    #[test]
+    fn expression_from_amplitudes() {
+        assert_eq!(expression(&default_amplitudes()), "1 * Cheb(x, 1)");
+        assert_eq!(
+            expression(&[0.0, -0.5, 0.25]),
+            "-0.5 * Cheb(x, 2) + 0.25 * Cheb(x, 3)"
+        );
+        assert_eq!(
+            expression(&[1.0, 0.00001, -0.5]),
+            "1 * Cheb(x, 1) - 0.5 * Cheb(x, 3)"
+        );
+        assert_eq!(expression(&[0.0; NUM_HARMONICS]), "0");
+    }
+
+    fn assert_amplitudes(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert!(
+                (actual - expected).abs() < 1e-5,
+                "expected {expected:?}, got {actual:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn analyze_chebyshev_series() {
+        assert_amplitudes(&analyze(|x| x, 1.0, 4), &[1.0, 0.0, 0.0, 0.0]);
+
+        let curve = |x: f32| {
+            let x = x as f64;
+            (0.5 * first_kind(x, 1.0) - 0.25 * first_kind(x, 3.0) + 0.125 * first_kind(x, 6.0))
+                as f32
+        };
+        assert_amplitudes(&analyze(curve, 1.0, 6), &[0.5, 0.0, 0.25, 0.0, 0.0, 0.125]);
+    }
+
+    #[test]
+    fn analyze_depends_on_amplitude() {
+        // (cos(t) / 2)^2 = 1/8 + cos(2t) / 8
+        assert_amplitudes(&analyze(|x| x * x, 0.5, 3), &[0.0, 0.125, 0.0]);
+    }
+
+    #[test]
+    fn spectra_pick_the_closest_level() {
+        let spectra = Spectra::analyze(|x| x * x, 1.0, 3);
+        assert_amplitudes(spectra.get(1.0), &[0.0, 0.5, 0.0]);
+        assert_amplitudes(spectra.get(0.5), &analyze(|x| x * x, 0.5012, 3));
+        assert_amplitudes(spectra.get(4.0), spectra.get(1.0));
+        assert_amplitudes(spectra.get(0.0), spectra.levels.last().unwrap());
+        assert!(Spectra::default().get(1.0).is_empty());
+    }
+
+    #[test]
+    fn analyze_ignores_non_finite_values() {
+        assert_amplitudes(&analyze(|_| f32::NAN, 1.0, 2), &[0.0, 0.0]);
+    }
+}
@@ -0,0 +1,50 @@
+use evalexpr::EvalexprError;
+
+use super::check_order;
+
+/// The physicists' Hermite polynomial, see [`polynomial()`].
+pub(crate) fn hermite(value: &f64, order: &i64) -> Result<f64, EvalexprError> {
+    check_order("Hermite", *order as f64)?;
+    Ok(polynomial(*value, *order as usize))
+}
+
+/// The physicists' Hermite polynomial H(n) for an already checked order, using the recurrence
+/// H(n + 1) = 2x H(n) - 2n H(n - 1).
+pub(crate) fn polynomial(x: f64, order: usize) -> f64 {
+    let (mut previous, mut current) = (1.0, 2.0 * x);
+    if order == 0 {
+        return previous;
+    }
+    for n in 1..order {
+        let n = n as f64;
+        (previous, current) = (current, 2.0 * x * current - 2.0 * n * previous);
+    }
+    current
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shaper::tests::assert_close;
+
+    #[test]
+    fn low_orders() {
+        for x in [-1.5, -0.4, 0.0, 0.3, 1.0, 2.0] {
+            assert_close(polynomial(x, 0), 1.0, 1e-12);
+            assert_close(polynomial(x, 1), 2.0 * x, 1e-12);
+            assert_close(polynomial(x, 2), 4.0 * x * x - 2.0, 1e-12);
+            assert_close(polynomial(x, 3), 8.0 * x * x * x - 12.0 * x, 1e-12);
+            assert_close(
+                polynomial(x, 4),
+                16.0 * x.powi(4) - 48.0 * x * x + 12.0,
+                1e-9,
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_orders() {
+        assert!(hermite(&0.5, &-1).is_err());
+        assert!(hermite(&0.5, &2000).is_err());
+    }
+}
@@ -0,0 +1,59 @@
+use evalexpr::EvalexprError;
+
+use super::check_order;
+
+/// The Laguerre polynomial, see [`polynomial()`].
+pub(crate) fn laguerre(value: &f64, order: &i64) -> Result<f64, EvalexprError> {
+    check_order("Laguerre", *order as f64)?;
+    Ok(polynomial(*value, *order as usize))
+}
+
+/// The Laguerre polynomial L(n) for an already checked order, using the recurrence
+/// (n + 1) L(n + 1) = (2n + 1 - x) L(n) - n L(n - 1).
+pub(crate) fn polynomial(x: f64, order: usize) -> f64 {
+    let (mut previous, mut current) = (1.0, 1.0 - x);
+    if order == 0 {
+        return previous;
+    }
+    for n in 1..order {
+        let n = n as f64;
+        (previous, current) = (
+            current,
+            ((2.0 * n + 1.0 - x) * current - n * previous) / (n + 1.0),
+        );
+    }
+    current
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shaper::tests::assert_close;
+
+    #[test]
+    fn low_orders() {
+        for x in [-1.0, 0.0, 0.3, 1.0, 2.5] {
+            assert_close(polynomial(x, 0), 1.0, 1e-12);
+            assert_close(polynomial(x, 1), 1.0 - x, 1e-12);
+            assert_close(polynomial(x, 2), (x * x - 4.0 * x + 2.0) / 2.0, 1e-12);
+            assert_close(
+                polynomial(x, 3),
+                (-x * x * x + 9.0 * x * x - 18.0 * x + 6.0) / 6.0,
+                1e-12,
+            );
+        }
+    }
+
+    #[test]
+    fn one_at_zero() {
+        for order in [0, 5, 64] {
+            assert_close(polynomial(0.0, order), 1.0, 1e-9);
+        }
+    }
+
+    #[test]
+    fn invalid_orders() {
+        assert!(laguerre(&0.5, &-1).is_err());
+        assert!(laguerre(&0.5, &2000).is_err());
+    }
+}
@@ -0,0 +1,58 @@
+use evalexpr::EvalexprError;
+
+use super::check_order;
+
+/// The Legendre polynomial, see [`polynomial()`].
+pub(crate) fn legendre(value: &f64, order: &i64) -> Result<f64, EvalexprError> {
+    check_order("Legendre", *order as f64)?;
+    Ok(polynomial(*value, *order as usize))
+}
+
+/// The Legendre polynomial P(n) for an already checked order, using Bonnet's recurrence
+/// (n + 1) P(n + 1) = (2n + 1) x P(n) - n P(n - 1).
+pub(crate) fn polynomial(x: f64, order: usize) -> f64 {
+    let (mut previous, mut current) = (1.0, x);
+    if order == 0 {
+        return previous;
+    }
+    for n in 1..order {
+        let n = n as f64;
+        (previous, current) = (
+            current,
+            ((2.0 * n + 1.0) * x * current - n * previous) / (n + 1.0),
+        );
+    }
+    current
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shaper::tests::assert_close;
+
+    #[test]
+    fn low_orders() {
+        for x in [-1.0, -0.4, 0.0, 0.3, 1.0, 1.5] {
+            assert_close(polynomial(x, 0), 1.0, 1e-12);
+            assert_close(polynomial(x, 1), x, 1e-12);
+            assert_close(polynomial(x, 2), (3.0 * x * x - 1.0) / 2.0, 1e-12);
+            assert_close(polynomial(x, 3), (5.0 * x * x * x - 3.0 * x) / 2.0, 1e-12);
+        }
+    }
+
+    #[test]
+    fn edges() {
+        // P(n) is one at 1 and alternates at -1 for every order
+        for order in [4, 7, 100] {
+            assert_close(polynomial(1.0, order), 1.0, 1e-9);
+            let sign = if order % 2 == 0 { 1.0 } else { -1.0 };
+            assert_close(polynomial(-1.0, order), sign, 1e-9);
+        }
+    }
+
+    #[test]
+    fn invalid_orders() {
+        assert!(legendre(&0.5, &-1).is_err());
+        assert!(legendre(&0.5, &2000).is_err());
+    }
+}
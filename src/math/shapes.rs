@@ -0,0 +1,323 @@
+/// A shaping function. Invalid parameters are reported as an error message instead of producing
+/// NaN, without allocating so the exact engine can call these on the audio thread.
+#[derive(Debug, Clone, Copy)]
+pub enum Function {
+    Unary(fn(f64) -> f64),
+    Binary(fn(f64, f64) -> Result<f64, &'static str>),
+    Ternary(fn(f64, f64, f64) -> Result<f64, &'static str>),
+}
+
+#[derive(Debug)]
+pub struct Shape {
+    pub name: &'static str,
+    pub function: Function,
+}
+
+impl Shape {
+    /// The number of arguments including the input.
+    pub fn arguments(&self) -> usize {
+        match self.function {
+            Function::Unary(_) => 1,
+            Function::Binary(_) => 2,
+            Function::Ternary(_) => 3,
+        }
+    }
+
+    /// Calls the function, `arguments` must have the length returned by
+    /// [`arguments()`][Self::arguments()].
+    pub fn call(&self, arguments: &[f64]) -> Result<f64, &'static str> {
+        match (self.function, arguments) {
+            (Function::Unary(function), &[x]) => Ok(function(x)),
+            (Function::Binary(function), &[x, a]) => function(x, a),
+            (Function::Ternary(function), &[x, a, b]) => function(x, a, b),
+            _ => Err("Wrong number of arguments"),
+        }
+    }
+}
+
+/// Common waveshaping curves available as functions in expressions. The input always comes first,
+/// followed by the curve's parameters.
+pub static SHAPES: &[Shape] = &[
+    Shape {
+        name: "softclip",
+        function: Function::Binary(softclip),
+    },
+    Shape {
+        name: "hardclip",
+        function: Function::Binary(hardclip),
+    },
+    Shape {
+        name: "fold",
+        function: Function::Binary(fold),
+    },
+    Shape {
+        name: "wrap",
+        function: Function::Binary(wrap),
+    },
+    Shape {
+        name: "sign",
+        function: Function::Unary(sign),
+    },
+    Shape {
+        name: "clamp",
+        function: Function::Ternary(clamp),
+    },
+    Shape {
+        name: "sinc",
+        function: Function::Unary(sinc),
+    },
+    Shape {
+        name: "erf",
+        function: Function::Unary(erf),
+    },
+    Shape {
+        name: "asinhsat",
+        function: Function::Binary(asinh_saturation),
+    },
+    Shape {
+        name: "tanhcubic",
+        function: Function::Unary(tanh_cubic),
+    },
+    Shape {
+        name: "tanhatan",
+        function: Function::Unary(tanh_arctan),
+    },
+    Shape {
+        name: "tanhpade",
+        function: Function::Unary(tanh_pade),
+    },
+    Shape {
+        name: "diode",
+        function: Function::Binary(diode),
+    },
+    Shape {
+        name: "tube",
+        function: Function::Binary(tube),
+    },
+];
+
+/// Looks up the shape called `name`.
+pub fn find(name: &str) -> Option<&'static Shape> {
+    SHAPES.iter().find(|shape| shape.name == name)
+}
+
+fn positive(value: f64, message: &'static str) -> Result<f64, &'static str> {
+    if value > 0.0 && value.is_finite() {
+        Ok(value)
+    } else {
+        Err(message)
+    }
+}
+
+/// `softclip(x, hardness)`: Saturates smoothly towards ±1 with unity gain around zero. A hardness
+/// of 1 gives `x / (1 + |x|)`, higher values get closer to [`hardclip()`].
+fn softclip(x: f64, hardness: f64) -> Result<f64, &'static str> {
+    let hardness = positive(hardness, "softclip hardness must be positive")?;
+    Ok(x / (1.0 + x.abs().powf(hardness)).powf(hardness.recip()))
+}
+
+/// `hardclip(x, limit)`: Clips at ±limit.
+fn hardclip(x: f64, limit: f64) -> Result<f64, &'static str> {
+    let limit = positive(limit, "hardclip limit must be positive")?;
+    Ok(x.clamp(-limit, limit))
+}
+
+/// `fold(x, limit)`: A wavefolder, reflects everything beyond ±limit back into the range.
+fn fold(x: f64, limit: f64) -> Result<f64, &'static str> {
+    let limit = positive(limit, "fold limit must be positive")?;
+    // A triangle wave with period 4 * limit that follows x around zero
+    let phase = (x + limit).rem_euclid(4.0 * limit);
+    Ok(limit - (phase - 2.0 * limit).abs())
+}
+
+/// `wrap(x, limit)`: Wraps everything beyond ±limit around to the other end of the range.
+fn wrap(x: f64, limit: f64) -> Result<f64, &'static str> {
+    let limit = positive(limit, "wrap limit must be positive")?;
+    Ok((x + limit).rem_euclid(2.0 * limit) - limit)
+}
+
+/// `sign(x)`: -1, 0 or 1.
+fn sign(x: f64) -> f64 {
+    if x > 0.0 {
+        1.0
+    } else if x < 0.0 {
+        -1.0
+    } else {
+        0.0
+    }
+}
+
+/// `clamp(x, low, high)`: Limits x to the range from low to high.
+fn clamp(x: f64, low: f64, high: f64) -> Result<f64, &'static str> {
+    if low <= high {
+        Ok(x.clamp(low, high))
+    } else {
+        Err("clamp needs the lower bound first")
+    }
+}
+
+/// `sinc(x)`: The normalized sinc function `sin(pi x) / (pi x)`.
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        let x = std::f64::consts::PI * x;
+        x.sin() / x
+    }
+}
+
+/// `erf(x)`: The error function, using the approximation from Abramowitz and Stegun 7.1.26
+/// which is accurate to about 1e-7.
+fn erf(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.327_591_1 * x.abs());
+    let polynomial = t
+        * (0.254_829_592
+            + t * (-0.284_496_736
+                + t * (1.421_413_741 + t * (-1.453_152_027 + t * 1.061_405_429))));
+    sign(x) * (1.0 - polynomial * (-x * x).exp())
+}
+
+/// `asinhsat(x, drive)`: `asinh(drive x) / asinh(drive)`, a gentle saturation that never flattens
+/// out completely, scaled so an input of 1 stays at 1.
+fn asinh_saturation(x: f64, drive: f64) -> Result<f64, &'static str> {
+    let drive = positive(drive, "asinhsat drive must be positive")?;
+    Ok((drive * x).asinh() / drive.asinh())
+}
+
+/// `tanhcubic(x)`: A cubic approximation of tanh, `x - 4x^3 / 27` up to ±1.5 and ±1 beyond.
+fn tanh_cubic(x: f64) -> f64 {
+    let x = x.clamp(-1.5, 1.5);
+    x - 4.0 * x * x * x / 27.0
+}
+
+/// `tanhatan(x)`: An arctangent shaped like tanh, `2 / pi * atan(pi / 2 x)`.
+fn tanh_arctan(x: f64) -> f64 {
+    std::f64::consts::FRAC_2_PI * (std::f64::consts::FRAC_PI_2 * x).atan()
+}
+
+/// `tanhpade(x)`: The Padé approximation `x (27 + x^2) / (27 + 9 x^2)` up to ±3 and ±1 beyond.
+fn tanh_pade(x: f64) -> f64 {
+    let x = x.clamp(-3.0, 3.0);
+    x * (27.0 + x * x) / (27.0 + 9.0 * x * x)
+}
+
+/// `diode(x, knee)`: A single diode clipper. Negative inputs pass unchanged, positive ones
+/// saturate exponentially towards `1 / knee`.
+fn diode(x: f64, knee: f64) -> Result<f64, &'static str> {
+    let knee = positive(knee, "diode knee must be positive")?;
+    Ok(if x > 0.0 {
+        -(-knee * x).exp_m1() / knee
+    } else {
+        x
+    })
+}
+
+/// `tube(x, bias)`: An asymmetric tube style curve, `tanh(x + bias) - tanh(bias)`. The bias
+/// shifts the operating point so one half of the waveform clips earlier than the other, while
+/// silence stays at zero.
+fn tube(x: f64, bias: f64) -> Result<f64, &'static str> {
+    if bias.is_finite() {
+        Ok((x + bias).tanh() - bias.tanh())
+    } else {
+        Err("tube bias must be finite")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shaper::tests::assert_close;
+
+    fn call(name: &str, arguments: &[f64]) -> Result<f64, &'static str> {
+        find(name).unwrap().call(arguments)
+    }
+
+    #[test]
+    fn names_are_unique() {
+        for (i, shape) in SHAPES.iter().enumerate() {
+            assert!(SHAPES[..i].iter().all(|other| other.name != shape.name));
+            assert!(std::ptr::eq(find(shape.name).unwrap(), shape));
+        }
+        assert!(find("Cheb").is_none());
+    }
+
+    #[test]
+    fn shapes_pass_through_the_origin() {
+        for shape in SHAPES.iter().filter(|shape| shape.name != "sinc") {
+            let mut arguments = [0.0, 1.0, 1.0];
+            if shape.name == "clamp" {
+                arguments[1] = -1.0;
+            }
+            let value = shape.call(&arguments[..shape.arguments()]).unwrap();
+            assert_close(value, 0.0, 1e-6);
+        }
+        assert_eq!(call("sinc", &[0.0]), Ok(1.0));
+    }
+
+    #[test]
+    fn wrong_argument_count() {
+        assert!(call("softclip", &[0.5]).is_err());
+        assert!(call("sign", &[0.5, 1.0]).is_err());
+    }
+
+    #[test]
+    fn invalid_parameters() {
+        assert!(call("softclip", &[0.5, 0.0]).is_err());
+        assert!(call("hardclip", &[0.5, -1.0]).is_err());
+        assert!(call("fold", &[0.5, f64::INFINITY]).is_err());
+        assert!(call("wrap", &[0.5, f64::NAN]).is_err());
+        assert!(call("clamp", &[0.5, 1.0, -1.0]).is_err());
+        assert!(call("asinhsat", &[0.5, 0.0]).is_err());
+        assert!(call("diode", &[0.5, -2.0]).is_err());
+        assert!(call("tube", &[0.5, f64::NAN]).is_err());
+    }
+
+    #[test]
+    fn clipping() {
+        assert_close(call("softclip", &[1.0, 1.0]).unwrap(), 0.5, 1e-6);
+        // Harder clipping gets close to one sooner, but never reaches it
+        let hard = call("softclip", &[2.0, 8.0]).unwrap();
+        assert!(hard > 0.99 && hard < 1.0);
+        assert_eq!(call("hardclip", &[3.0, 0.5]), Ok(0.5));
+        assert_eq!(call("hardclip", &[-3.0, 0.5]), Ok(-0.5));
+        assert_eq!(call("clamp", &[3.0, -1.0, 2.0]), Ok(2.0));
+        assert_close(call("tanhcubic", &[1.5]).unwrap(), 1.0, 1e-6);
+        assert_close(call("tanhcubic", &[4.0]).unwrap(), 1.0, 1e-6);
+        assert_close(call("tanhpade", &[-5.0]).unwrap(), -1.0, 1e-6);
+        assert_close(call("tanhatan", &[1e9]).unwrap(), 1.0, 1e-6);
+    }
+
+    #[test]
+    fn folding_and_wrapping() {
+        assert_close(call("fold", &[0.3, 1.0]).unwrap(), 0.3, 1e-6);
+        assert_close(call("fold", &[1.3, 1.0]).unwrap(), 0.7, 1e-6);
+        assert_close(call("fold", &[-2.5, 1.0]).unwrap(), 0.5, 1e-6);
+        assert_close(call("wrap", &[0.3, 1.0]).unwrap(), 0.3, 1e-6);
+        assert_close(call("wrap", &[1.3, 1.0]).unwrap(), -0.7, 1e-6);
+        assert_close(call("wrap", &[-1.5, 1.0]).unwrap(), 0.5, 1e-6);
+    }
+
+    #[test]
+    fn special_functions() {
+        assert_eq!(call("sign", &[-0.1]), Ok(-1.0));
+        assert_eq!(call("sign", &[0.0]), Ok(0.0));
+        assert_close(call("sinc", &[1.0]).unwrap(), 0.0, 1e-6);
+        assert_close(
+            call("sinc", &[0.5]).unwrap(),
+            2.0 / std::f64::consts::PI,
+            1e-6,
+        );
+        assert_close(call("erf", &[1.0]).unwrap(), 0.842_700_79, 1e-6);
+        assert_close(call("erf", &[-0.5]).unwrap(), -0.520_499_88, 1e-6);
+        assert_close(call("asinhsat", &[1.0, 5.0]).unwrap(), 1.0, 1e-6);
+    }
+
+    #[test]
+    fn asymmetric_shapes() {
+        assert_eq!(call("diode", &[-2.0, 1.0]), Ok(-2.0));
+        assert_close(call("diode", &[100.0, 2.0]).unwrap(), 0.5, 1e-6);
+        let positive = call("tube", &[1.0, 0.5]).unwrap();
+        let negative = call("tube", &[-1.0, 0.5]).unwrap();
+        assert!(positive < -negative);
+    }
+}
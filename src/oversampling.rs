@@ -0,0 +1,171 @@
+use nih_plug::prelude::Enum;
+use valib::oversample::Oversample;
+
+/// How much the signal gets oversampled before shaping it.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OversamplingFactor {
+    #[name = "1x"]
+    X1,
+    #[name = "2x"]
+    X2,
+    #[name = "4x"]
+    X4,
+    #[name = "8x"]
+    X8,
+    #[name = "16x"]
+    X16,
+}
+
+impl OversamplingFactor {
+    pub fn factor(self) -> usize {
+        1 << self.to_index()
+    }
+}
+
+/// Resamplers for every oversampling factor and channel. These are all allocated up front so the
+/// factor can be changed on the audio thread.
+pub struct Oversampling {
+    /// Indexed by factor and then by channel. There's nothing to allocate for 1x.
+    resamplers: Box<[Box<[Oversample<f32>]>]>,
+    /// The latency of each factor in samples at the base rate.
+    latencies: Box<[u32]>,
+    /// The factor the last block was processed with.
+    factor: OversamplingFactor,
+}
+
+impl Default for Oversampling {
+    fn default() -> Self {
+        Self::new(0, 1)
+    }
+}
+
+impl Oversampling {
+    pub fn new(channels: usize, max_block_size: usize) -> Self {
+        let factors = OversamplingFactor::variants().len();
+        let resamplers = (1..factors)
+            .map(|index| {
+                let factor = OversamplingFactor::from_index(index).factor();
+                vec![Oversample::<f32>::new(factor, max_block_size); channels].into_boxed_slice()
+            })
+            .collect();
+        let latencies = (0..factors)
+            .map(|index| measure_latency(OversamplingFactor::from_index(index), max_block_size))
+            .collect();
+
+        Self {
+            resamplers,
+            latencies,
+            factor: OversamplingFactor::X1,
+        }
+    }
+
+    /// Clears the state of all resamplers.
+    pub fn reset(&mut self) {
+        for channels in self.resamplers.iter_mut() {
+            for resampler in channels.iter_mut() {
+                resampler.reset();
+            }
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.resamplers.first().map_or(0, |channels| channels.len())
+    }
+
+    /// The delay `factor` adds, in samples at the base rate.
+    pub fn latency(&self, factor: OversamplingFactor) -> u32 {
+        self.latencies[factor.to_index()]
+    }
+
+    /// The largest delay any factor adds.
+    pub fn max_latency(&self) -> u32 {
+        self.latencies.iter().copied().max().unwrap_or(0)
+    }
+
+    /// Upsamples `buffer` by `factor`, lets `f` process the upsampled signal and then writes the
+    /// downsampled result back into `buffer`. `buffer` may not be longer than the maximum block
+    /// size.
+    pub fn process(
+        &mut self,
+        factor: OversamplingFactor,
+        channel: usize,
+        buffer: &mut [f32],
+        f: impl FnOnce(&mut [f32]),
+    ) {
+        // The resamplers of a factor that wasn't used for a while still contain whatever was
+        // playing back then
+        if factor != self.factor {
+            self.factor = factor;
+            if let Some(index) = factor.to_index().checked_sub(1) {
+                for resampler in self.resamplers[index].iter_mut() {
+                    resampler.reset();
+                }
+            }
+        }
+
+        match factor.to_index().checked_sub(1) {
+            None => f(buffer),
+            Some(index) => {
+                let mut oversampled_block = self.resamplers[index][channel].oversample(buffer);
+                f(&mut oversampled_block);
+                oversampled_block.finish(buffer);
+            }
+        }
+    }
+}
+
+/// Finds the delay of the resampling filters by sending an impulse through a fresh resampler and
+/// looking for the output's peak.
+fn measure_latency(factor: OversamplingFactor, max_block_size: usize) -> u32 {
+    if factor == OversamplingFactor::X1 {
+        return 0;
+    }
+
+    let mut resampler = Oversample::<f32>::new(factor.factor(), max_block_size);
+    let mut buffer = vec![0.0; max_block_size];
+    buffer[0] = 1.0;
+    resampler.oversample(&buffer).finish(&mut buffer);
+
+    buffer
+        .iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.abs().total_cmp(&b.abs()))
+        .map_or(0, |(index, _)| index as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delay::DelayLine;
+
+    /// The index of the loudest sample.
+    fn peak(buffer: &[f32]) -> usize {
+        buffer
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.abs().total_cmp(&b.abs()))
+            .map_or(0, |(index, _)| index)
+    }
+
+    #[test]
+    fn dry_delay_lines_up_with_the_wet_signal() {
+        const BLOCK_SIZE: usize = 64;
+        let mut oversampling = Oversampling::new(1, BLOCK_SIZE);
+        for index in 0..OversamplingFactor::variants().len() {
+            let factor = OversamplingFactor::from_index(index);
+            let latency = oversampling.latency(factor) as usize;
+            let mut delay = DelayLine::new(oversampling.max_latency() as usize);
+
+            let mut wet = [0.0; BLOCK_SIZE];
+            wet[0] = 1.0;
+            let dry: Vec<f32> = wet
+                .iter()
+                .map(|&sample| delay.process(sample, latency))
+                .collect();
+            oversampling.process(factor, 0, &mut wet, |_| ());
+
+            assert_eq!(peak(&dry), latency);
+            assert_eq!(peak(&wet), latency, "at {}x", factor.factor());
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+use nih_plug::prelude::nih_log;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+
+use crate::generator::Generator;
+
+/// A text file the expression can be loaded from. Persisted with the plugin state, so this works
+/// the same on every machine the project is opened on as long as the file exists there.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PromptFileSettings {
+    pub path: String,
+    /// Reload the file whenever it is saved.
+    pub watch: bool,
+}
+
+/// Loads expressions from the prompt file, and watches it for changes if that is enabled.
+pub struct PromptFile {
+    settings: Arc<RwLock<PromptFileSettings>>,
+    generator: Arc<Generator>,
+    watcher: Mutex<Option<RecommendedWatcher>>,
+}
+
+impl PromptFile {
+    pub fn new(settings: Arc<RwLock<PromptFileSettings>>, generator: Arc<Generator>) -> Self {
+        Self {
+            settings,
+            generator,
+            watcher: Mutex::new(None),
+        }
+    }
+
+    pub fn settings(&self) -> PromptFileSettings {
+        self.settings.read().unwrap().clone()
+    }
+
+    pub fn set_path(&self, path: String) {
+        self.settings.write().unwrap().path = path;
+        self.sync();
+    }
+
+    pub fn set_watch(&self, watch: bool) {
+        self.settings.write().unwrap().watch = watch;
+        self.sync();
+    }
+
+    /// Reads the prompt file and commits its contents as the new expression. Returns the id of
+    /// the request, which still needs to be run in a background task. Read errors are reported
+    /// through the generator's message.
+    pub fn load(&self) -> Option<u64> {
+        let path = self.settings.read().unwrap().path.clone();
+        if path.is_empty() {
+            self.generator
+                .set_message("No prompt file has been set".to_owned());
+            return None;
+        }
+        load(Path::new(&path), &self.generator)
+    }
+
+    /// Starts or stops the file watcher to match the settings. Needs to be called again after the
+    /// settings were restored.
+    pub fn sync(&self) {
+        let settings = self.settings();
+        let mut watcher = self.watcher.lock().unwrap();
+        *watcher = None;
+        if !settings.watch || settings.path.is_empty() {
+            return;
+        }
+
+        match watch(PathBuf::from(settings.path), self.generator.clone()) {
+            Ok(new_watcher) => *watcher = Some(new_watcher),
+            Err(err) => self
+                .generator
+                .set_message(format!("Failed to watch the prompt file: {err}")),
+        }
+    }
+}
+
+fn load(path: &Path, generator: &Generator) -> Option<u64> {
+    match fs::read_to_string(path) {
+        Ok(expression) => {
+            let mut curve = generator.settings();
+            curve.expression = expression;
+            Some(generator.commit(curve))
+        }
+        Err(err) => {
+            generator.set_message(format!(
+                "Failed to read prompt file '{}': {err}",
+                path.display()
+            ));
+            None
+        }
+    }
+}
+
+/// Watches the directory containing `path` rather than the file itself, since many editors save
+/// by replacing the file.
+fn watch(path: PathBuf, generator: Arc<Generator>) -> notify::Result<RecommendedWatcher> {
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_owned(),
+        _ => PathBuf::from("."),
+    };
+    let file_name = path.file_name().map(|name| name.to_owned());
+
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let event = match event {
+            Ok(event) => event,
+            Err(err) => {
+                nih_log!("Error while watching the prompt file: {err}");
+                return;
+            }
+        };
+        let is_prompt_file = event
+            .paths
+            .iter()
+            .any(|changed| changed.file_name() == file_name.as_deref());
+        if is_prompt_file && matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+            // This runs on the watcher's own thread, so the curve can be built right here
+            if let Some(id) = load(&path, &generator) {
+                generator.run(id);
+            }
+        }
+    })?;
+    watcher.watch(&directory, RecursiveMode::NonRecursive)?;
+    Ok(watcher)
+}
@@ -1,120 +1,652 @@
 use std::usize;
 
+use check::{check, Argument};
 use evalexpr::{
-    build_operator_tree, context_map, ContextWithMutableVariables, EvalexprError, HashMapContext,
-    Value,
+    build_operator_tree, context_map, ContextWithMutableFunctions, ContextWithMutableVariables,
+    EvalexprError, Function, HashMapContext, Value,
 };
+use interpolation::spline_second_derivatives;
+pub use interpolation::Interpolation;
 use nih_plug_vizia::vizia::{
     context::DrawContext,
     vg::{self, Color},
     view::Canvas,
 };
+pub use normalization::Normalization;
+use normalization::Transform;
+use program::Program;
+use serde::{Deserialize, Serialize};
 
-use crate::math::chebychev::chebychev;
+use crate::error::ShaperError;
+use crate::math::bernstein::{bernstein, bezier};
+use crate::math::chebychev::{chebychev, chebychev_second_kind};
+use crate::math::hermite::hermite;
+use crate::math::laguerre::laguerre;
+use crate::math::legendre::legendre;
+use crate::math::shapes::SHAPES;
 
-pub struct Shaper<const SIZE: usize> {
-    table: Box<[f32]>,
+mod check;
+mod interpolation;
+mod normalization;
+mod program;
+mod script;
+mod syntax;
+
+/// The names of the macro parameters as they appear in expressions.
+pub const MACRO_NAMES: [&str; NUM_MACROS] = ["a", "b", "c", "d"];
+pub const NUM_MACROS: usize = 4;
+/// The constants defined in [`Shaper::default_context()`], and the sample rate.
+const CONSTANT_NAMES: [&str; 4] = ["PI", "E", "TAU", "SR"];
+pub type Macros = [f32; NUM_MACROS];
+
+/// The range of table sizes that can be chosen, in entries.
+pub const MIN_TABLE_SIZE: usize = 256;
+pub const MAX_TABLE_SIZE: usize = 65536;
+pub const DEFAULT_TABLE_SIZE: usize = 512;
+
+/// The range of input domains that can be chosen, as the largest input value. The domain is
+/// always symmetric around zero.
+pub const MIN_INPUT_MAX: f32 = 1.0;
+pub const MAX_INPUT_MAX: f32 = 16.0;
+
+/// The most table entries [`Shaper::display()`] draws lines between.
+const MAX_DISPLAY_POINTS: usize = 2048;
+
+/// How many table entries are evaluated between checks whether the build was cancelled.
+const CANCEL_INTERVAL: usize = 64;
+
+/// Argument types of the custom functions in [`Shaper::default_context()`]. Calls to these are
+/// checked before evaluating so errors can point at the call.
+const SIGNATURES: &[(&str, &[Argument])] = &[
+    ("Cheb", &[Argument::Number, Argument::Number]),
+    ("ChebU", &[Argument::Number, Argument::Int]),
+    ("Legendre", &[Argument::Number, Argument::Int]),
+    ("Hermite", &[Argument::Number, Argument::Int]),
+    ("Laguerre", &[Argument::Number, Argument::Int]),
+    (
+        "Bernstein",
+        &[Argument::Number, Argument::Int, Argument::Int],
+    ),
+    ("Bezier", &[Argument::Number; 5]),
+];
+
+/// What to do with NaN or infinite values in a freshly generated table.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NonFinitePolicy {
+    /// Keep the last good curve and report an error.
+    Reject,
+    /// Replace them by interpolating between the closest finite neighbours.
+    #[default]
+    Interpolate,
+    /// Replace infinities by the largest or smallest finite value in the table, and NaN by zero.
+    Clamp,
+}
+
+impl NonFinitePolicy {
+    pub fn next(self) -> Self {
+        match self {
+            NonFinitePolicy::Reject => NonFinitePolicy::Interpolate,
+            NonFinitePolicy::Interpolate => NonFinitePolicy::Clamp,
+            NonFinitePolicy::Clamp => NonFinitePolicy::Reject,
+        }
+    }
+}
+
+impl std::fmt::Display for NonFinitePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NonFinitePolicy::Reject => write!(f, "Reject"),
+            NonFinitePolicy::Interpolate => write!(f, "Interpolate"),
+            NonFinitePolicy::Clamp => write!(f, "Clamp"),
+        }
+    }
+}
+
+/// What happens to input values outside of the shaper's domain.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Extrapolation {
+    /// Hold the value at the nearest edge.
+    #[default]
+    Clamp,
+    /// Continue with the slope at the nearest edge.
+    Linear,
+    /// Fold the input back into the domain, as if mirrored at its edges.
+    Mirror,
+    /// Repeat the curve periodically.
+    Wrap,
+}
+
+impl Extrapolation {
+    pub fn next(self) -> Self {
+        match self {
+            Extrapolation::Clamp => Extrapolation::Linear,
+            Extrapolation::Linear => Extrapolation::Mirror,
+            Extrapolation::Mirror => Extrapolation::Wrap,
+            Extrapolation::Wrap => Extrapolation::Clamp,
+        }
+    }
+}
+
+impl std::fmt::Display for Extrapolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Extrapolation::Clamp => write!(f, "Clamp"),
+            Extrapolation::Linear => write!(f, "Linear"),
+            Extrapolation::Mirror => write!(f, "Mirror"),
+            Extrapolation::Wrap => write!(f, "Wrap"),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Shaper {
+    /// Between [`MIN_TABLE_SIZE`] and [`MAX_TABLE_SIZE`] entries, evenly spread over the input
+    /// range.
+    table: Vec<f32>,
+    interpolation: Interpolation,
+    /// Only used by [`Interpolation::NaturalSpline`].
+    second_derivatives: Vec<f32>,
+    /// The first and second antiderivative of the linearly interpolated table at every entry, both
+    /// zero at the lower edge of the domain. Used for antiderivative anti-aliasing.
+    antiderivatives: Vec<f64>,
+    second_antiderivatives: Vec<f64>,
+    /// The table covers inputs from `-input_max` to `input_max`.
+    input_max: f32,
+    extrapolation: Extrapolation,
     context: HashMapContext,
+    /// The expression compiled for the exact engine. Empty if it could not be compiled.
+    program: Program,
+    program_error: Option<ShaperError>,
+    /// Applied to the output of `program` so it matches the normalized table.
+    transform: Transform,
 }
 
-impl<const SIZE: usize> Default for Shaper<SIZE> {
+impl Default for Shaper {
     fn default() -> Self {
-        let table: Box<[f32]> = (0..SIZE).map(Shaper::<SIZE>::value).collect();
-        Self {
-            table,
-            context: Shaper::<SIZE>::default_context(),
-        }
+        Self::identity(DEFAULT_TABLE_SIZE, MIN_INPUT_MAX)
     }
 }
 
-impl<const SIZE: usize> Shaper<SIZE> {
-    const INDEX_MAX: usize = SIZE - 1;
-    const INPUT_SAMPLE_MAX: f32 = 1.0;
-    const INPUT_SAMPLE_MIN: f32 = -Self::INPUT_SAMPLE_MAX;
-    const STEP: f32 = 2.0 / Self::INDEX_MAX as f32;
+impl Shaper {
+    /// The identity function with a table of `size` entries covering inputs from `-input_max` to
+    /// `input_max`. Both get clamped to the supported ranges.
+    pub fn identity(size: usize, input_max: f32) -> Self {
+        let mut this = Self {
+            table: Vec::new(),
+            interpolation: Interpolation::default(),
+            second_derivatives: Vec::new(),
+            antiderivatives: Vec::new(),
+            second_antiderivatives: Vec::new(),
+            input_max: input_max.clamp(MIN_INPUT_MAX, MAX_INPUT_MAX),
+            extrapolation: Extrapolation::default(),
+            context: Self::default_context(),
+            program: Program::default(),
+            program_error: None,
+            transform: Transform::default(),
+        };
+        this.resize(size);
+        this
+    }
+
+    /// The identity function, with room for the largest table. [`copy_from()`][Self::copy_from()]
+    /// never allocates on a shaper created this way.
+    pub fn preallocated() -> Self {
+        let mut this = Self::default();
+        this.table.reserve_exact(MAX_TABLE_SIZE - this.table.len());
+        this.second_derivatives
+            .reserve_exact(MAX_TABLE_SIZE - this.second_derivatives.len());
+        this.antiderivatives
+            .reserve_exact(MAX_TABLE_SIZE - this.antiderivatives.len());
+        this.second_antiderivatives
+            .reserve_exact(MAX_TABLE_SIZE - this.second_antiderivatives.len());
+        this
+    }
+
+    /// Resets the table to the identity function with `size` entries.
+    fn resize(&mut self, size: usize) {
+        let size = size.clamp(MIN_TABLE_SIZE, MAX_TABLE_SIZE);
+        self.table.clear();
+        self.table.resize(size, 0.0);
+        for i in 0..size {
+            self.table[i] = self.value(i);
+        }
+        self.second_derivatives.clear();
+        self.second_derivatives.resize(size, 0.0);
+        self.update_antiderivatives();
+    }
+
+    pub fn size(&self) -> usize {
+        self.table.len()
+    }
+
+    fn index_max(&self) -> usize {
+        self.table.len() - 1
+    }
+
+    fn step(&self) -> f32 {
+        2.0 * self.input_max / self.index_max() as f32
+    }
+
+    pub fn input_max(&self) -> f32 {
+        self.input_max
+    }
 
     fn default_context() -> HashMapContext {
-        context_map! {
+        let mut context = context_map! {
             "PI" => evalexpr::Value::Float(std::f64::consts::PI),
+            "E" => evalexpr::Value::Float(std::f64::consts::E),
+            "TAU" => evalexpr::Value::Float(std::f64::consts::TAU),
             "Cheb" => Function::new(|args| {
-                let args = args.as_tuple()?;
-                if let (Value::Float(x), Value::Int(n)) = (&args[0], &args[1]) {
-                    Ok(Value::Float(chebychev(x, n)?))
-                } else {
-                    Err(EvalexprError::expected_number(args[0].clone())) // TODO: improve error reporting
-                }
+                let args = args.as_fixed_len_tuple(2)?;
+                Ok(Value::Float(chebychev(&args[0].as_number()?, &args[1].as_number()?)?))
+            }),
+            "ChebU" => Function::new(|args| {
+                let args = args.as_fixed_len_tuple(2)?;
+                Ok(Value::Float(chebychev_second_kind(&args[0].as_number()?, &args[1].as_int()?)?))
+            }),
+            "Legendre" => Function::new(|args| {
+                let args = args.as_fixed_len_tuple(2)?;
+                Ok(Value::Float(legendre(&args[0].as_number()?, &args[1].as_int()?)?))
+            }),
+            "Hermite" => Function::new(|args| {
+                let args = args.as_fixed_len_tuple(2)?;
+                Ok(Value::Float(hermite(&args[0].as_number()?, &args[1].as_int()?)?))
+            }),
+            "Laguerre" => Function::new(|args| {
+                let args = args.as_fixed_len_tuple(2)?;
+                Ok(Value::Float(laguerre(&args[0].as_number()?, &args[1].as_int()?)?))
+            }),
+            "Bernstein" => Function::new(|args| {
+                let args = args.as_fixed_len_tuple(3)?;
+                Ok(Value::Float(bernstein(
+                    &args[0].as_number()?,
+                    &args[1].as_int()?,
+                    &args[2].as_int()?,
+                )?))
+            }),
+            "Bezier" => Function::new(|args| {
+                let args = args.as_fixed_len_tuple(5)?;
+                let points = [
+                    args[1].as_number()?,
+                    args[2].as_number()?,
+                    args[3].as_number()?,
+                    args[4].as_number()?,
+                ];
+                Ok(Value::Float(bezier(args[0].as_number()?, points)))
             }),
         }
-        .expect("Failed to initialize contex map!")
+        .expect("Failed to initialize contex map!");
+
+        for shape in SHAPES {
+            let function = Function::new(move |args| {
+                let mut arguments = [0.0; 3];
+                let arguments = &mut arguments[..shape.arguments()];
+                match arguments {
+                    [x] => *x = args.as_number()?,
+                    _ => {
+                        let args = args.as_fixed_len_tuple(arguments.len())?;
+                        for (argument, value) in arguments.iter_mut().zip(&args) {
+                            *argument = value.as_number()?;
+                        }
+                    }
+                }
+                shape
+                    .call(arguments)
+                    .map(Value::Float)
+                    .map_err(|message| EvalexprError::CustomMessage(message.to_owned()))
+            });
+            context
+                .set_function(shape.name.to_owned(), function)
+                .expect("Failed to add shaping function");
+        }
+        context
     }
 
-    #[allow(unused)]
-    fn new(prompt: &str) -> Result<Self, EvalexprError> {
-        let mut this = Self::default();
-        this.prompt(prompt)?;
+    pub fn new(
+        prompt: &str,
+        size: usize,
+        input_max: f32,
+        macros: &Macros,
+        sample_rate: f32,
+        continuous: bool,
+        cancel: &dyn Fn() -> bool,
+    ) -> Result<Self, ShaperError> {
+        let mut this = Self::identity(size, input_max);
+        this.prompt(prompt, macros, sample_rate, continuous, cancel)?;
         Ok(this)
     }
 
-    #[allow(unused)] // TODO: remove
     pub fn process(&self, x: f32) -> f32 {
-        self.lerp(Self::index(x), x)
+        self.extrapolate(x, |x| self.lookup(x))
     }
 
-    fn index(value: f32) -> usize {
-        (((value - Self::INPUT_SAMPLE_MIN) / Self::STEP) as usize).min(Self::INDEX_MAX)
+    /// Interpolates the table at `x`, which should be inside the domain.
+    fn lookup(&self, x: f32) -> f32 {
+        let index_max = self.index_max();
+        let position = ((x + self.input_max) / self.step()).clamp(0.0, index_max as f32);
+        let index = (position as usize).min(index_max - 1);
+        self.interpolation.interpolate(
+            &self.table,
+            &self.second_derivatives,
+            index,
+            position - index as f32,
+        )
     }
 
-    pub fn value(index: usize) -> f32 {
-        Self::INPUT_SAMPLE_MIN + (index as f32 * Self::STEP)
+    /// Sets how the table is read. This needs to be called again whenever the table changes
+    /// afterwards, the natural spline is computed here.
+    pub fn set_interpolation(&mut self, interpolation: Interpolation) {
+        self.interpolation = interpolation;
+        if interpolation == Interpolation::NaturalSpline {
+            spline_second_derivatives(&self.table, &mut self.second_derivatives);
+        }
+    }
+
+    pub fn set_extrapolation(&mut self, extrapolation: Extrapolation) {
+        self.extrapolation = extrapolation;
     }
 
-    fn lerp(&self, index: usize, x: f32) -> f32 {
-        if index == Self::INDEX_MAX {
-            return self.table[Self::INDEX_MAX];
+    /// Applies `f`, which is only defined inside the domain, to `x` according to the extrapolation
+    /// mode.
+    fn extrapolate(&self, x: f32, f: impl Fn(f32) -> f32) -> f32 {
+        let max = self.input_max;
+        if (-max..=max).contains(&x) {
+            return f(x);
+        }
+
+        let width = 2.0 * max;
+        match self.extrapolation {
+            Extrapolation::Clamp => f(x.clamp(-max, max)),
+            Extrapolation::Linear => {
+                // The slope comes from the table in both modes, so they agree
+                let last = self.index_max();
+                let (edge, slope) = if x > max {
+                    (max, (self.table[last] - self.table[last - 1]) / self.step())
+                } else {
+                    (-max, (self.table[1] - self.table[0]) / self.step())
+                };
+                f(edge) + slope * (x - edge)
+            }
+            Extrapolation::Mirror => {
+                let position = (x + max).rem_euclid(2.0 * width);
+                let position = if position > width {
+                    2.0 * width - position
+                } else {
+                    position
+                };
+                f(position - max)
+            }
+            Extrapolation::Wrap => f((x + max).rem_euclid(width) - max),
+        }
+    }
+
+    /// Recomputes the antiderivative tables. This needs to be called whenever the table changes.
+    pub fn update_antiderivatives(&mut self) {
+        let h = self.step() as f64;
+        let size = self.size();
+        self.antiderivatives.clear();
+        self.antiderivatives.resize(size, 0.0);
+        self.second_antiderivatives.clear();
+        self.second_antiderivatives.resize(size, 0.0);
+        for i in 0..size - 1 {
+            let y1 = self.table[i] as f64;
+            let y2 = self.table[i + 1] as f64;
+            let f1 = self.antiderivatives[i];
+            self.antiderivatives[i + 1] = f1 + h * (y1 + y2) / 2.0;
+            self.second_antiderivatives[i + 1] = self.second_antiderivatives[i]
+                + f1 * h
+                + y1 * h * h / 2.0
+                + (y2 - y1) * h * h / 6.0;
+        }
+    }
+
+    /// Whether [`antiderivative()`][Self::antiderivative()] and
+    /// [`second_antiderivative()`][Self::second_antiderivative()] match [`process()`][Self::process()]
+    /// at `x`. The antiderivatives don't know about mirrored or wrapped inputs.
+    pub fn has_antiderivatives_at(&self, x: f64) -> bool {
+        matches!(
+            self.extrapolation,
+            Extrapolation::Clamp | Extrapolation::Linear
+        ) || x.abs() <= self.input_max as f64
+    }
+
+    /// Finds the linear piece of the curve `x` lies on. Returns the table entry it starts at, the
+    /// distance from that entry and the slope of the piece.
+    fn segment(&self, x: f64) -> (usize, f64, f64) {
+        let h = self.step() as f64;
+        let min = -self.input_max as f64;
+        let last = self.index_max();
+        let slope = |index: usize| (self.table[index + 1] - self.table[index]) as f64 / h;
+        let edge_slope = |index: usize| match self.extrapolation {
+            Extrapolation::Linear => slope(index),
+            _ => 0.0,
         };
-        let higher_index = index + 1;
-        let y1 = self.table[index];
-        let x1 = Self::value(index);
-        let y2 = self.table[higher_index];
-        let x2 = Self::value(higher_index);
 
-        let delta_y = y1 - y2;
-        let delta_x = x1 - x2;
-        let position = (x - x1) / delta_x;
-        y1 + (delta_y * position)
+        let position = (x - min) / h;
+        if position < 0.0 {
+            (0, x - min, edge_slope(0))
+        } else if position >= last as f64 {
+            (last, x - min - last as f64 * h, edge_slope(last - 1))
+        } else {
+            let index = position as usize;
+            (index, x - min - index as f64 * h, slope(index))
+        }
+    }
+
+    /// The first antiderivative of the linearly interpolated table, including the extrapolation.
+    pub fn antiderivative(&self, x: f64) -> f64 {
+        let (index, d, slope) = self.segment(x);
+        self.antiderivatives[index] + self.table[index] as f64 * d + slope * d * d / 2.0
     }
 
-    pub fn normalize(&mut self) {
-        let max_abs = self
-            .table
-            .iter()
-            .map(|&value| value.abs())
-            .max_by(|a, b| a.partial_cmp(b).expect("NaN Error"))
-            .expect("Table can't be empty");
+    /// The second antiderivative of the linearly interpolated table, including the extrapolation.
+    pub fn second_antiderivative(&self, x: f64) -> f64 {
+        let (index, d, slope) = self.segment(x);
+        self.second_antiderivatives[index]
+            + self.antiderivatives[index] * d
+            + self.table[index] as f64 * d * d / 2.0
+            + slope * d * d * d / 6.0
+    }
+
+    /// Evaluates the compiled expression instead of interpolating the table. This falls back to
+    /// the table if the expression could not be compiled or the result is not finite.
+    pub fn process_exact(&self, x: f32, macros: &Macros) -> f32 {
+        if self.program.is_empty() {
+            return self.process(x);
+        }
+        self.extrapolate(x, |x| {
+            let y = self.transform.apply(self.program.eval(x, macros));
+            if y.is_finite() {
+                y
+            } else {
+                self.lookup(x)
+            }
+        })
+    }
 
+    /// Why the exact engine can't be used for this curve, if it can't.
+    pub fn program_error(&self) -> Option<&ShaperError> {
+        self.program_error.as_ref()
+    }
+
+    /// Copies the curve of `source` into this shaper, so the audio thread can keep a curve around
+    /// after the triple buffer has moved on. Only what [`process()`] and [`process_exact()`] need
+    /// is copied, the compile error is not. This doesn't allocate as long as this shaper was
+    /// created with [`preallocated()`].
+    ///
+    /// [`process()`]: Self::process()
+    /// [`process_exact()`]: Self::process_exact()
+    /// [`preallocated()`]: Self::preallocated()
+    pub fn copy_from(&mut self, source: &Self) {
+        self.table.clear();
+        self.table.extend_from_slice(&source.table);
+        self.interpolation = source.interpolation;
+        self.second_derivatives.clear();
+        self.second_derivatives
+            .extend_from_slice(&source.second_derivatives);
+        self.antiderivatives.clear();
+        self.antiderivatives
+            .extend_from_slice(&source.antiderivatives);
+        self.second_antiderivatives.clear();
+        self.second_antiderivatives
+            .extend_from_slice(&source.second_antiderivatives);
+        self.input_max = source.input_max;
+        self.extrapolation = source.extrapolation;
+        self.program.copy_from(&source.program);
+        self.transform = source.transform;
+    }
+
+    /// The input value table entry `index` belongs to.
+    pub fn value(&self, index: usize) -> f32 {
+        -self.input_max + (index as f32 * self.step())
+    }
+
+    /// Normalizes the table according to `mode`. This is not cumulative, the table is only
+    /// normalized once per generation. Tables that can't be normalized, like ones that are zero
+    /// everywhere, are left untouched.
+    pub fn normalize(&mut self, mode: Normalization) {
+        let step = self.step();
+        let slope_at_origin = (self.lookup(step) - self.lookup(-step)) / (2.0 * step);
+        let transform = Transform::new(mode, &self.table, slope_at_origin, self.lookup(0.0));
         for value in self.table.iter_mut() {
-            *value = *value / max_abs;
+            *value = transform.apply(*value);
+        }
+        self.transform = transform;
+    }
+
+    /// Checks the table for NaN or infinite entries and deals with them according to `policy`.
+    /// This needs to run before a table is published, the audio thread can't handle them.
+    pub fn validate(&mut self, policy: NonFinitePolicy) -> Result<(), ShaperError> {
+        let Some(first) = self.table.iter().position(|value| !value.is_finite()) else {
+            return Ok(());
+        };
+        let count = self.table.iter().filter(|value| !value.is_finite()).count();
+        let error = ShaperError::NonFinite {
+            index: first,
+            x: self.value(first),
+            value: self.table[first],
+            count,
+        };
+        if count == self.size() {
+            return Err(error);
+        }
+
+        match policy {
+            NonFinitePolicy::Reject => return Err(error),
+            NonFinitePolicy::Interpolate => self.interpolate_non_finite(),
+            NonFinitePolicy::Clamp => {
+                let finite = self.table.iter().copied().filter(|value| value.is_finite());
+                let min = finite.clone().fold(f32::INFINITY, f32::min);
+                let max = finite.fold(f32::NEG_INFINITY, f32::max);
+                for value in self.table.iter_mut() {
+                    *value = if value.is_nan() {
+                        0.0f32.clamp(min, max)
+                    } else {
+                        value.clamp(min, max)
+                    };
+                }
+            }
         }
+        Ok(())
     }
 
-    pub fn prompt(&mut self, prompt: &str) -> Result<(), EvalexprError> {
-        let node = build_operator_tree(prompt)?;
-        for (i, val) in self.table.iter_mut().enumerate() {
+    /// Replaces every run of non-finite entries by a line between its finite neighbours. Runs at
+    /// the edges of the table take the value of their only neighbour.
+    fn interpolate_non_finite(&mut self) {
+        let size = self.size();
+        let mut i = 0;
+        while i < size {
+            if self.table[i].is_finite() {
+                i += 1;
+                continue;
+            }
+
+            let start = i;
+            while i < size && !self.table[i].is_finite() {
+                i += 1;
+            }
+            let before = start.checked_sub(1).map(|index| self.table[index]);
+            let after = (i < size).then(|| self.table[i]);
+            for index in start..i {
+                self.table[index] = match (before, after) {
+                    (Some(y1), Some(y2)) => {
+                        let position = (index + 1 - start) as f32 / (i + 1 - start) as f32;
+                        y1 + (y2 - y1) * position
+                    }
+                    (Some(y), None) | (None, Some(y)) => y,
+                    // `validate()` makes sure there is at least one finite value
+                    (None, None) => unreachable!(),
+                };
+            }
+        }
+    }
+
+    /// Regenerates the table from `prompt`, which can be a script as described in
+    /// [`script::expand()`]. The script sees `sample_rate` as `SR`, and `continuous` makes the
+    /// pieces of piecewise curves meet. `cancel` is polled every now and then, once it returns
+    /// true this gives up with [`ShaperError::Cancelled`].
+    pub fn prompt(
+        &mut self,
+        prompt: &str,
+        macros: &Macros,
+        sample_rate: f32,
+        continuous: bool,
+        cancel: &dyn Fn() -> bool,
+    ) -> Result<(), ShaperError> {
+        let script = script::expand(prompt, continuous)?;
+        let expression = script.text();
+        let node = build_operator_tree(expression)
+            .map_err(|err| script.map_error(ShaperError::parse(err, expression), prompt))?;
+        for (name, value) in MACRO_NAMES.iter().zip(macros) {
             self.context
-                .set_value(
-                    "x".to_owned(),
-                    evalexpr::Value::Float(Self::value(i) as f64),
-                )
+                .set_value(name.to_string(), Value::Float(*value as f64))
                 .expect("Failed to set context!");
-            *val = node.eval_float_with_context(&self.context)? as f32;
         }
+        self.context
+            .set_value("SR".to_owned(), Value::Float(sample_rate as f64))
+            .expect("Failed to set context!");
+        self.set_x(self.value(0));
+        check(&node, expression, &self.context, SIGNATURES)
+            .map_err(|err| script.map_error(err, prompt))?;
+
+        for i in 0..self.size() {
+            if i % CANCEL_INTERVAL == 0 && cancel() {
+                return Err(ShaperError::Cancelled);
+            }
+            let x = self.value(i);
+            self.set_x(x);
+            self.table[i] = node
+                .eval_number_with_context(&self.context)
+                .map_err(|err| script.map_error(ShaperError::eval(err, expression, i, x), prompt))?
+                as f32;
+        }
+
+        match Program::compile(&node, expression, &self.context) {
+            Ok(program) => {
+                self.program = program;
+                self.program_error = None;
+            }
+            Err(err) => {
+                self.program = Program::empty();
+                self.program_error = Some(script.map_error(err, prompt));
+            }
+        }
+        self.transform = Transform::default();
         Ok(())
     }
 
+    fn set_x(&mut self, x: f32) {
+        self.context
+            .set_value("x".to_owned(), Value::Float(x as f64))
+            .expect("Failed to set context!");
+    }
+
     pub fn display(&self, cx: &mut DrawContext, canvas: &mut Canvas) {
         let bounds = cx.bounds();
         let line_width = cx.scale_factor() * 1.5;
-        let x_step = bounds.w / Self::INDEX_MAX as f32;
+        let x_step = bounds.w / self.index_max() as f32;
+        // Large tables have far more entries than there are pixels
+        let stride = (self.size() / MAX_DISPLAY_POINTS).max(1);
 
         let plot_paint = vg::Paint::color(Color::rgb(0, 255, 0)).with_line_width(line_width);
         let mut plot = vg::Path::new();
@@ -122,7 +654,13 @@ impl<const SIZE: usize> Shaper<SIZE> {
             bounds.x,
             bounds.y + (bounds.h / 2.0) - ((bounds.h / 2.0) * self.table[0]),
         );
-        for (i, y) in self.table.iter().enumerate() {
+        for (i, y) in self
+            .table
+            .iter()
+            .enumerate()
+            .step_by(stride)
+            .chain(self.table.last().map(|y| (self.index_max(), y)))
+        {
             plot.line_to(
                 bounds.x + (i as f32 * x_step),
                 bounds.y + (bounds.h / 2.0) - ((bounds.h / 2.0) * y),
@@ -132,90 +670,104 @@ impl<const SIZE: usize> Shaper<SIZE> {
     }
 }
 
-// #[cfg(test)]
-// mod test {
-
-//     use plotly::{Plot, Scatter};
-//     use rand::random;
-
-//     use crate::shaper::Shaper as GenericShaper;
-
-//     const TABLE_SIZE: usize = 32;
-
-//     type Shaper = GenericShaper<TABLE_SIZE>;
-
-//     #[test]
-//     fn test_floats() {
-//         assert_eq!(f32::MIN, (f32::MIN as f64) as f32);
-//         assert_ne!((f32::MIN / 1.24436) as f64, f32::MIN as f64 / 1.24436); // NOT EQUAL!
-//         assert_ne!(
-//             (f32::MIN / 1.24436_f32) as f64,
-//             f32::MIN as f64 / 1.24436_f32 as f64
-//         ); // NOT EQUAL!
-//         let step = f32::from_bits(0b00000000000000000000000000000001);
-//         println!("{}", step);
-//     }
-
-//     #[test]
-//     fn print_default_lut() {}
-
-//     #[test]
-//     fn test_value_to_index() {
-//         let index = Shaper::index(Shaper::INPUT_SAMPLE_MIN);
-//         println!("Testing if index is 0 with input SAMPE_MIN:");
-//         println!("\tindex:          {}", index);
-//         println!("\texpected index: {}", 0);
-//         assert_eq!(index, 0);
-
-//         let index = Shaper::index(Shaper::INPUT_SAMPLE_MAX);
-//         println!("Testing if index is max index with input SAMPLE_MAX:");
-//         println!("\tindex:          {}", index);
-//         println!("\texpected index: {}", Shaper::INDEX_MAX);
-//         assert_eq!(index, Shaper::INDEX_MAX);
-
-//         let index = Shaper::index(Shaper::INPUT_SAMPLE_MAX + 2.0);
-//         println!("Testing if index is max index with input out of range:");
-//         println!("\tindex:          {}", index);
-//         println!("\texpected index: {}", Shaper::INDEX_MAX);
-//         assert_eq!(index, Shaper::INDEX_MAX);
-//     }
-
-//     #[test]
-//     fn test_interpolate() {
-//         let shaper = Shaper::default();
-//         for _ in 0..1000 {
-//             let x = Shaper::INPUT_SAMPLE_MIN + random::<f32>() + random::<f32>();
-//             let y = shaper.lerp(Shaper::index(x), x);
-//             assert_eq!(x, y)
-//         }
-//     }
-
-//     #[test]
-//     fn test_prompt() {
-//         let x_trace: Vec<f32> = (0..TABLE_SIZE).map(Shaper::value).collect();
-//         let mut shaper = Shaper::default();
-//         let default_trace = Scatter::new(x_trace.clone(), shaper.table.clone().into())
-//             .mode(plotly::common::Mode::Markers)
-//             .name("LUT Default");
-//         shaper.prompt("math::sin(3 * PI * x)").unwrap();
-//         let prompt_trace = Scatter::new(x_trace, shaper.table.clone().into())
-//             .mode(plotly::common::Mode::Markers)
-//             .name("LUT Prompt");
-//         let mut random_x = Vec::new();
-//         let mut random_y = Vec::new();
-//         for _ in 0..8192 {
-//             let x = Shaper::INPUT_SAMPLE_MIN + (2.0 * random::<f32>());
-//             random_x.push(x);
-//             let y = shaper.process(x);
-//             random_y.push(y);
-//         }
-
-//         let random_trace = Scatter::new(random_x, random_y)
-//             .mode(plotly::common::Mode::Markers)
-//             .name("Random");
-
-//         let mut plot = Plot::new();
-//         plot.add_traces(vec![default_trace, prompt_trace, random_trace]);
-//         plot.write_html("plot.html");
-//     }
-// }
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Builds a curve over `[-input_max, input_max]` with the macros at zero, for the tests here
+    /// and in the modules built on top of the shaper.
+    pub(crate) fn shaper(prompt: &str, input_max: f32) -> Shaper {
+        Shaper::new(
+            prompt,
+            1024,
+            input_max,
+            &[0.0; NUM_MACROS],
+            48000.0,
+            false,
+            &|| false,
+        )
+        .unwrap()
+    }
+
+    /// The tolerance is relative for values larger than one.
+    pub(crate) fn assert_close(actual: impl Into<f64>, expected: impl Into<f64>, tolerance: f64) {
+        let (actual, expected) = (actual.into(), expected.into());
+        assert!(
+            (actual - expected).abs() < tolerance * expected.abs().max(1.0),
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    #[test]
+    fn extrapolation_inside_the_domain() {
+        let mut shaper = shaper("x^3", 1.0);
+        for extrapolation in [
+            Extrapolation::Clamp,
+            Extrapolation::Linear,
+            Extrapolation::Mirror,
+            Extrapolation::Wrap,
+        ] {
+            shaper.set_extrapolation(extrapolation);
+            assert_close(shaper.process(0.5), 0.125, 1e-3);
+            assert_close(shaper.process(-1.0), -1.0, 1e-3);
+        }
+    }
+
+    #[test]
+    fn extrapolation_clamp() {
+        let mut shaper = shaper("x^3", 1.0);
+        shaper.set_extrapolation(Extrapolation::Clamp);
+        assert_eq!(shaper.process(3.0), shaper.process(1.0));
+        assert_eq!(shaper.process(-3.0), shaper.process(-1.0));
+    }
+
+    #[test]
+    fn extrapolation_linear() {
+        let mut shaper = shaper("x^3", 1.0);
+        shaper.set_extrapolation(Extrapolation::Linear);
+        // The slope of x^3 at the edges is 3
+        assert!((shaper.process(2.0) - 4.0).abs() < 1e-2);
+        assert!((shaper.process(-2.0) + 4.0).abs() < 1e-2);
+        assert!((shaper.process_exact(2.0, &[0.0; NUM_MACROS]) - 4.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn extrapolation_mirror() {
+        let mut shaper = shaper("x^3", 1.0);
+        shaper.set_extrapolation(Extrapolation::Mirror);
+        assert_close(shaper.process(1.5), 0.125, 1e-3);
+        assert_close(shaper.process(-1.5), -0.125, 1e-3);
+        assert_close(shaper.process(3.5), -0.125, 1e-3);
+        assert_close(shaper.process_exact(1.5, &[0.0; NUM_MACROS]), 0.125, 1e-3);
+    }
+
+    #[test]
+    fn extrapolation_wrap() {
+        let mut shaper = shaper("x^3", 1.0);
+        shaper.set_extrapolation(Extrapolation::Wrap);
+        assert_close(shaper.process(1.5), -0.125, 1e-3);
+        assert_close(shaper.process(-1.5), 0.125, 1e-3);
+        assert_close(shaper.process(4.5), 0.125, 1e-3);
+        assert_close(shaper.process_exact(1.5, &[0.0; NUM_MACROS]), -0.125, 1e-3);
+    }
+
+    #[test]
+    fn input_domain() {
+        let shaper = Shaper::new(
+            "x / 4",
+            512,
+            4.0,
+            &[0.0; NUM_MACROS],
+            48000.0,
+            false,
+            &|| false,
+        )
+        .unwrap();
+        assert_eq!(shaper.input_max(), 4.0);
+        assert_close(shaper.process(-4.0), -1.0, 1e-3);
+        assert_close(shaper.process(2.0), 0.5, 1e-3);
+
+        let shaper = Shaper::identity(512, 100.0);
+        assert_eq!(shaper.input_max(), MAX_INPUT_MAX);
+    }
+}
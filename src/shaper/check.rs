@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+
+use evalexpr::{Context, HashMapContext, Node, Operator, Value};
+
+use crate::error::{ShaperError, Span};
+use crate::math::shapes;
+
+/// The type a custom function expects for one of its arguments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Argument {
+    Number,
+    Int,
+}
+
+impl Argument {
+    fn description(self) -> &'static str {
+        match self {
+            Argument::Number => "a number",
+            Argument::Int => "an integer",
+        }
+    }
+
+    fn accepts(self, value: &Value) -> bool {
+        match self {
+            Argument::Number => value.is_number(),
+            Argument::Int => value.is_int(),
+        }
+    }
+}
+
+/// Checks the operator tree of `source` before it gets evaluated. This catches unknown
+/// variables, incomplete operators and wrong calls to the functions in `signatures` with a
+/// location in the source, which the evaluation errors of evalexpr don't have.
+pub fn check(
+    node: &Node,
+    source: &str,
+    context: &HashMapContext,
+    signatures: &[(&str, &[Argument])],
+) -> Result<(), ShaperError> {
+    Checker {
+        source,
+        context,
+        signatures,
+        occurrences: HashMap::new(),
+    }
+    .check(node)
+}
+
+struct Checker<'a> {
+    source: &'a str,
+    context: &'a HashMapContext,
+    signatures: &'a [(&'a str, &'a [Argument])],
+    /// How often each identifier has been seen so far. The tree is walked in source order, so
+    /// this is used to find the matching occurrence in the source text.
+    occurrences: HashMap<&'a str, usize>,
+}
+
+impl<'a> Checker<'a> {
+    fn check(&mut self, node: &'a Node) -> Result<(), ShaperError> {
+        match node.operator() {
+            Operator::VariableIdentifierRead { identifier } => {
+                let span = self.span(identifier);
+                if self.context.get_value(identifier).is_none() {
+                    return Err(ShaperError::UnknownIdentifier {
+                        identifier: identifier.clone(),
+                        span,
+                    });
+                }
+            }
+            Operator::FunctionIdentifier { identifier } => {
+                let span = self.span(identifier);
+                if let Some((_, signature)) =
+                    self.signatures.iter().find(|(name, _)| name == identifier)
+                {
+                    check_call(identifier, signature, arguments(node), span)?;
+                } else if let Some(shape) = shapes::find(identifier) {
+                    let signature = &[Argument::Number; 3][..shape.arguments()];
+                    check_call(identifier, signature, arguments(node), span)?;
+                }
+            }
+            operator => {
+                let expected = match operator {
+                    Operator::Neg | Operator::Not => Some(1),
+                    Operator::Add
+                    | Operator::Sub
+                    | Operator::Mul
+                    | Operator::Div
+                    | Operator::Mod
+                    | Operator::Exp
+                    | Operator::Eq
+                    | Operator::Neq
+                    | Operator::Gt
+                    | Operator::Lt
+                    | Operator::Geq
+                    | Operator::Leq
+                    | Operator::And
+                    | Operator::Or => Some(2),
+                    _ => None,
+                };
+                if expected.is_some_and(|expected| node.children().len() != expected) {
+                    return Err(ShaperError::Parse {
+                        message: format!("Operator '{operator}' is missing an operand"),
+                        span: Span::whole(self.source),
+                    });
+                }
+            }
+        }
+
+        node.children()
+            .iter()
+            .try_for_each(|child| self.check(child))
+    }
+
+    fn span(&mut self, identifier: &'a str) -> Span {
+        let nth = self.occurrences.entry(identifier).or_default();
+        let span = Span::identifier(self.source, identifier, *nth);
+        *nth += 1;
+        span.unwrap_or_else(|| Span::whole(self.source))
+    }
+}
+
+fn check_call(
+    function: &str,
+    signature: &[Argument],
+    arguments: &[Node],
+    span: Span,
+) -> Result<(), ShaperError> {
+    if arguments.len() != signature.len() {
+        return Err(ShaperError::ArgumentCount {
+            function: function.to_owned(),
+            expected: signature.len()..=signature.len(),
+            actual: arguments.len(),
+            span,
+        });
+    }
+
+    // Only constants can be checked up front, everything else is caught while evaluating
+    for (i, (expected, argument)) in signature.iter().zip(arguments).enumerate() {
+        if let Some(value) = constant(argument) {
+            if !expected.accepts(value) {
+                return Err(ShaperError::ArgumentType {
+                    function: function.to_owned(),
+                    argument: i,
+                    expected: expected.description(),
+                    actual: value.to_string(),
+                    span,
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The argument nodes of a function call. `f(a, b)` is parsed as a function node with a root
+/// node child that holds a tuple, while `f(a)` has no tuple in between.
+pub(super) fn arguments(node: &Node) -> &[Node] {
+    let mut arguments = node.children();
+    while let [argument] = arguments {
+        match argument.operator() {
+            Operator::RootNode => arguments = argument.children(),
+            Operator::Tuple => return argument.children(),
+            _ => break,
+        }
+    }
+    arguments
+}
+
+fn constant(node: &Node) -> Option<&Value> {
+    match node.operator() {
+        Operator::Const { value } => Some(value),
+        Operator::RootNode => match node.children() {
+            [child] => constant(child),
+            _ => None,
+        },
+        _ => None,
+    }
+}
@@ -0,0 +1,214 @@
+use serde::{Deserialize, Serialize};
+
+/// How values between table entries are read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Interpolation {
+    /// The closest entry, this produces a staircase.
+    Nearest,
+    #[default]
+    Linear,
+    /// Cubic Hermite with monotone tangents. Smooth, but never overshoots the table values.
+    CubicHermite,
+    /// Cubic Hermite with central difference tangents. Smoother than [`Self::CubicHermite`] but
+    /// may overshoot around sharp knees.
+    CatmullRom,
+    /// A natural cubic spline through all entries. This has a continuous second derivative, but
+    /// needs [`spline_second_derivatives()`] to be computed for the table.
+    NaturalSpline,
+}
+
+impl Interpolation {
+    pub fn next(self) -> Self {
+        match self {
+            Interpolation::Nearest => Interpolation::Linear,
+            Interpolation::Linear => Interpolation::CubicHermite,
+            Interpolation::CubicHermite => Interpolation::CatmullRom,
+            Interpolation::CatmullRom => Interpolation::NaturalSpline,
+            Interpolation::NaturalSpline => Interpolation::Nearest,
+        }
+    }
+
+    /// Reads `table` at `index + t`, where `t` is between 0 and 1 and `index + 1` is still a valid
+    /// index. `second_derivatives` is only used by [`Self::NaturalSpline`].
+    pub fn interpolate(
+        self,
+        table: &[f32],
+        second_derivatives: &[f32],
+        index: usize,
+        t: f32,
+    ) -> f32 {
+        let y1 = table[index];
+        let y2 = table[index + 1];
+        match self {
+            Interpolation::Nearest => {
+                if t < 0.5 {
+                    y1
+                } else {
+                    y2
+                }
+            }
+            Interpolation::Linear => y1 + (y2 - y1) * t,
+            Interpolation::CubicHermite | Interpolation::CatmullRom => {
+                // The outermost segments reuse their end points as the missing neighbours
+                let y0 = table[index.saturating_sub(1)];
+                let y3 = table[(index + 2).min(table.len() - 1)];
+                let (m1, m2) = if self == Interpolation::CatmullRom {
+                    ((y2 - y0) / 2.0, (y3 - y1) / 2.0)
+                } else {
+                    (
+                        monotone_tangent(y1 - y0, y2 - y1),
+                        monotone_tangent(y2 - y1, y3 - y2),
+                    )
+                };
+                hermite(y1, y2, m1, m2, t)
+            }
+            Interpolation::NaturalSpline => {
+                let s = 1.0 - t;
+                y1 * s
+                    + y2 * t
+                    + ((s * s * s - s) * second_derivatives[index]
+                        + (t * t * t - t) * second_derivatives[index + 1])
+                        / 6.0
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Interpolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Interpolation::Nearest => write!(f, "Nearest"),
+            Interpolation::Linear => write!(f, "Linear"),
+            Interpolation::CubicHermite => write!(f, "Cubic Hermite"),
+            Interpolation::CatmullRom => write!(f, "Catmull-Rom"),
+            Interpolation::NaturalSpline => write!(f, "Natural Spline"),
+        }
+    }
+}
+
+/// Computes the second derivatives of the natural cubic spline through the evenly spaced points in
+/// `table` and writes them to `out`, which needs to have the same length.
+pub fn spline_second_derivatives(table: &[f32], out: &mut [f32]) {
+    let n = table.len();
+    out.fill(0.0);
+    if n < 3 {
+        return;
+    }
+
+    // Solves M[i - 1] + 4 M[i] + M[i + 1] = 6 (y[i + 1] - 2 y[i] + y[i - 1]) with M[0] and
+    // M[n - 1] being zero using the Thomas algorithm. `out` holds the modified right hand side on
+    // the way down.
+    let mut upper = vec![0.0f32; n];
+    for i in 1..n - 1 {
+        let rhs = 6.0 * (table[i + 1] - 2.0 * table[i] + table[i - 1]);
+        let denominator = 4.0 - upper[i - 1];
+        upper[i] = 1.0 / denominator;
+        out[i] = (rhs - out[i - 1]) / denominator;
+    }
+    for i in (1..n - 1).rev() {
+        out[i] -= upper[i] * out[i + 1];
+    }
+}
+
+fn hermite(y1: f32, y2: f32, m1: f32, m2: f32, t: f32) -> f32 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    (2.0 * t3 - 3.0 * t2 + 1.0) * y1
+        + (t3 - 2.0 * t2 + t) * m1
+        + (-2.0 * t3 + 3.0 * t2) * y2
+        + (t3 - t2) * m2
+}
+
+/// The tangent between two segments with slopes `d1` and `d2` that keeps the curve monotone, the
+/// harmonic mean of both or zero at a local extremum.
+fn monotone_tangent(d1: f32, d2: f32) -> f32 {
+    if d1 * d2 <= 0.0 {
+        0.0
+    } else {
+        2.0 * d1 * d2 / (d1 + d2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODES: [Interpolation; 5] = [
+        Interpolation::Nearest,
+        Interpolation::Linear,
+        Interpolation::CubicHermite,
+        Interpolation::CatmullRom,
+        Interpolation::NaturalSpline,
+    ];
+
+    fn interpolate(mode: Interpolation, table: &[f32], index: usize, t: f32) -> f32 {
+        let mut second_derivatives = vec![0.0; table.len()];
+        spline_second_derivatives(table, &mut second_derivatives);
+        mode.interpolate(table, &second_derivatives, index, t)
+    }
+
+    #[test]
+    fn passes_through_entries() {
+        let table = [0.0, 0.5, -0.25, 1.0, 0.75];
+        for mode in MODES {
+            for index in 0..table.len() - 1 {
+                assert_eq!(
+                    interpolate(mode, &table, index, 0.0),
+                    table[index],
+                    "{mode}"
+                );
+                assert_eq!(
+                    interpolate(mode, &table, index, 1.0),
+                    table[index + 1],
+                    "{mode}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn reproduces_lines() {
+        let table = [-1.0, -0.5, 0.0, 0.5, 1.0];
+        for mode in MODES.into_iter().skip(1) {
+            let value = interpolate(mode, &table, 1, 0.25);
+            assert!((value + 0.375).abs() < 1e-6, "{mode} gives {value}");
+        }
+    }
+
+    #[test]
+    fn nearest_rounds_to_the_closest_entry() {
+        let table = [0.0, 1.0];
+        assert_eq!(interpolate(Interpolation::Nearest, &table, 0, 0.49), 0.0);
+        assert_eq!(interpolate(Interpolation::Nearest, &table, 0, 0.5), 1.0);
+    }
+
+    #[test]
+    fn cubic_hermite_never_overshoots() {
+        let table = [0.0, 0.0, 1.0, 1.0];
+        for i in 0..=10 {
+            let t = i as f32 / 10.0;
+            for index in 0..table.len() - 1 {
+                let value = interpolate(Interpolation::CubicHermite, &table, index, t);
+                assert!((0.0..=1.0).contains(&value), "{value} at {index} + {t}");
+            }
+        }
+        // Catmull-Rom does overshoot after the knee
+        assert!(interpolate(Interpolation::CatmullRom, &table, 2, 0.5) > 1.0);
+    }
+
+    #[test]
+    fn spline_second_derivatives_are_natural() {
+        // x^2 sampled at whole numbers has a second derivative of 2 everywhere, the natural
+        // boundary forces it to zero at the ends
+        let table: Vec<f32> = (0..32).map(|i| (i * i) as f32).collect();
+        let mut second_derivatives = vec![1.0; table.len()];
+        spline_second_derivatives(&table, &mut second_derivatives);
+        assert_eq!(second_derivatives[0], 0.0);
+        assert_eq!(second_derivatives[31], 0.0);
+        assert!((second_derivatives[16] - 2.0).abs() < 1e-3);
+
+        let line = [0.0, 1.0, 2.0, 3.0];
+        spline_second_derivatives(&line, &mut second_derivatives[..4]);
+        assert_eq!(&second_derivatives[..4], &[0.0; 4]);
+    }
+}
@@ -0,0 +1,253 @@
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// How a freshly generated curve gets scaled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Normalization {
+    /// Leave the curve as it is.
+    #[default]
+    Off,
+    /// Scale the curve so its largest absolute value is one.
+    Peak,
+    /// Remove the curve's average value before scaling it by its peak.
+    PeakWithoutDc,
+    /// Scale the curve so its slope at the origin is plus or minus one, so quiet signals pass at
+    /// unity gain.
+    UnitySlope,
+    /// Shift the curve so that f(0) = 0, then scale the positive and negative halves separately
+    /// so both peak at one.
+    Symmetric,
+}
+
+impl Normalization {
+    pub fn next(self) -> Self {
+        match self {
+            Normalization::Off => Normalization::Peak,
+            Normalization::Peak => Normalization::PeakWithoutDc,
+            Normalization::PeakWithoutDc => Normalization::UnitySlope,
+            Normalization::UnitySlope => Normalization::Symmetric,
+            Normalization::Symmetric => Normalization::Off,
+        }
+    }
+
+    /// Older versions stored normalization as a plain on/off flag, which meant peak
+    /// normalization.
+    pub fn deserialize_legacy<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Stored {
+            Enabled(bool),
+            Mode(Normalization),
+        }
+
+        Ok(match Stored::deserialize(deserializer)? {
+            Stored::Enabled(true) => Normalization::Peak,
+            Stored::Enabled(false) => Normalization::Off,
+            Stored::Mode(mode) => mode,
+        })
+    }
+}
+
+impl std::fmt::Display for Normalization {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Normalization::Off => write!(f, "Off"),
+            Normalization::Peak => write!(f, "Peak"),
+            Normalization::PeakWithoutDc => write!(f, "Peak w/o DC"),
+            Normalization::UnitySlope => write!(f, "Unity Slope"),
+            Normalization::Symmetric => write!(f, "Symmetric"),
+        }
+    }
+}
+
+/// Maps a curve's output to its normalized output. This is kept separately from the table so the
+/// exact engine can apply the same normalization.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    offset: f32,
+    /// Applied to values at or above zero after subtracting the offset.
+    gain: f32,
+    /// Applied to values below zero after subtracting the offset.
+    negative_gain: f32,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            offset: 0.0,
+            gain: 1.0,
+            negative_gain: 1.0,
+        }
+    }
+}
+
+impl Transform {
+    /// Finds the transform that normalizes `table` according to `mode`. `slope_at_origin` and
+    /// `value_at_origin` are the curve's derivative and value at zero. Non-finite entries are
+    /// ignored, and whenever the curve can't be normalized, for instance because it is zero
+    /// everywhere, this returns the identity.
+    pub fn new(
+        mode: Normalization,
+        table: &[f32],
+        slope_at_origin: f32,
+        value_at_origin: f32,
+    ) -> Self {
+        let finite = || table.iter().copied().filter(|value| value.is_finite());
+        let peak = |offset: f32| {
+            finite()
+                .map(|value| (value - offset).abs())
+                .fold(0.0, f32::max)
+        };
+        let scale = |offset: f32, peak: f32| {
+            if peak > 0.0 && peak.is_finite() {
+                Self {
+                    offset,
+                    gain: peak.recip(),
+                    negative_gain: peak.recip(),
+                }
+            } else {
+                Self::default()
+            }
+        };
+
+        match mode {
+            Normalization::Off => Self::default(),
+            Normalization::Peak => scale(0.0, peak(0.0)),
+            Normalization::PeakWithoutDc => {
+                let count = finite().count();
+                if count == 0 {
+                    return Self::default();
+                }
+                let mean = (finite().map(|value| value as f64).sum::<f64>() / count as f64) as f32;
+                scale(mean, peak(mean))
+            }
+            Normalization::UnitySlope => scale(0.0, slope_at_origin.abs()),
+            Normalization::Symmetric => {
+                let offset = if value_at_origin.is_finite() {
+                    value_at_origin
+                } else {
+                    0.0
+                };
+                let max = finite().map(|value| value - offset).fold(0.0, f32::max);
+                let min = finite().map(|value| value - offset).fold(0.0, f32::min);
+                let gain = |peak: f32| if peak > 0.0 { peak.recip() } else { 1.0 };
+                Self {
+                    offset,
+                    gain: gain(max),
+                    negative_gain: gain(-min),
+                }
+            }
+        }
+    }
+
+    pub fn apply(&self, value: f32) -> f32 {
+        let value = value - self.offset;
+        if value >= 0.0 {
+            value * self.gain
+        } else {
+            value * self.negative_gain
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::de::{value::Error, IntoDeserializer};
+
+    use super::*;
+
+    const TABLE: [f32; 5] = [-4.0, -1.0, 0.5, 1.0, 2.0];
+
+    fn transform(mode: Normalization, table: &[f32]) -> Transform {
+        Transform::new(mode, table, 0.5, 0.5)
+    }
+
+    fn normalized(mode: Normalization, table: &[f32]) -> Vec<f32> {
+        let transform = transform(mode, table);
+        table.iter().map(|&value| transform.apply(value)).collect()
+    }
+
+    #[test]
+    fn off() {
+        assert_eq!(normalized(Normalization::Off, &TABLE), TABLE);
+    }
+
+    #[test]
+    fn peak() {
+        assert_eq!(
+            normalized(Normalization::Peak, &TABLE),
+            [-1.0, -0.25, 0.125, 0.25, 0.5]
+        );
+    }
+
+    #[test]
+    fn peak_without_dc() {
+        // The mean is -0.3, so -4 is the furthest away at -3.7
+        let values = normalized(Normalization::PeakWithoutDc, &TABLE);
+        assert!((values[0] + 1.0).abs() < 1e-6);
+        assert!((values[4] - 2.3 / 3.7).abs() < 1e-6);
+        assert!(values.iter().sum::<f32>().abs() < 1e-5);
+    }
+
+    #[test]
+    fn unity_slope() {
+        assert_eq!(
+            normalized(Normalization::UnitySlope, &TABLE),
+            [-8.0, -2.0, 1.0, 2.0, 4.0]
+        );
+        assert_eq!(
+            Transform::new(Normalization::UnitySlope, &TABLE, -0.25, 0.0).apply(1.0),
+            4.0
+        );
+    }
+
+    #[test]
+    fn symmetric() {
+        // Both halves are measured from the value at the origin, which is 0.5
+        assert_eq!(
+            normalized(Normalization::Symmetric, &TABLE),
+            [-1.0, -1.5 / 4.5, 0.0, 1.0 / 3.0, 1.0]
+        );
+    }
+
+    #[test]
+    fn unnormalizable_tables_are_left_alone() {
+        let silent = [0.0; 4];
+        for mode in [
+            Normalization::Peak,
+            Normalization::PeakWithoutDc,
+            Normalization::Symmetric,
+        ] {
+            assert_eq!(
+                Transform::new(mode, &silent, 0.0, 0.0),
+                Transform::default(),
+                "{mode}"
+            );
+        }
+        assert_eq!(
+            Transform::new(Normalization::UnitySlope, &TABLE, 0.0, 0.0),
+            Transform::default()
+        );
+        assert_eq!(
+            transform(Normalization::PeakWithoutDc, &[f32::NAN; 4]),
+            Transform::default()
+        );
+    }
+
+    #[test]
+    fn non_finite_entries_are_ignored() {
+        let table = [f32::NEG_INFINITY, -1.0, f32::NAN, 0.5];
+        assert_eq!(transform(Normalization::Peak, &table).apply(-1.0), -1.0);
+    }
+
+    #[test]
+    fn legacy_flag() {
+        fn legacy(value: impl IntoDeserializer<'static, Error>) -> Normalization {
+            Normalization::deserialize_legacy(value.into_deserializer()).unwrap()
+        }
+        assert_eq!(legacy(true), Normalization::Peak);
+        assert_eq!(legacy(false), Normalization::Off);
+        assert_eq!(legacy("UnitySlope"), Normalization::UnitySlope);
+    }
+}
@@ -0,0 +1,696 @@
+use evalexpr::{Context, HashMapContext, Node, Operator, Value};
+
+use super::{check::arguments, Macros, MACRO_NAMES};
+use crate::error::{ShaperError, Span};
+use crate::math::bernstein::{self, bezier, MAX_BERNSTEIN_ORDER};
+use crate::math::shapes::{self, Shape};
+use crate::math::{chebychev, hermite, is_valid_order, laguerre, legendre};
+
+/// The deepest value stack a program may need. Evaluation uses a fixed size array on the stack so
+/// it never allocates.
+const MAX_STACK: usize = 32;
+/// The most operations a program may consist of. Programs reserve this much space up front so
+/// [`Program::copy_from()`] never has to allocate.
+const MAX_OPS: usize = 256;
+/// The most arguments a function supported by the exact engine takes.
+const MAX_ARGUMENTS: usize = 5;
+
+/// The type evalexpr would give a value. Arithmetic on two integers stays an integer, so `1 / 2`
+/// is zero, and integers never compare equal to floats. Keeping track of this makes the exact
+/// engine agree with the table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Int,
+    Float,
+    Boolean,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Number {
+    value: f64,
+    kind: Kind,
+}
+
+impl Number {
+    fn float(value: f64) -> Self {
+        Self {
+            value,
+            kind: Kind::Float,
+        }
+    }
+
+    fn boolean(value: bool) -> Self {
+        Self {
+            value: if value { 1.0 } else { 0.0 },
+            kind: Kind::Boolean,
+        }
+    }
+
+    /// The result of an arithmetic operation on `a` and `b`, which is an integer if both are.
+    fn arithmetic(a: Self, b: Self, value: f64) -> Self {
+        let kind = if a.kind == Kind::Int && b.kind == Kind::Int {
+            Kind::Int
+        } else {
+            Kind::Float
+        };
+        Self { value, kind }
+    }
+
+    fn is_int(self) -> bool {
+        self.kind == Kind::Int
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Const(Number),
+    X,
+    Macro(usize),
+    Neg,
+    /// Unlike the other functions this keeps integers.
+    Abs,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    Eq,
+    Neq,
+    Gt,
+    Lt,
+    Geq,
+    Leq,
+    And,
+    Or,
+    Not,
+    /// Pops the condition and both branches, like evalexpr's `if` both branches are evaluated.
+    Select,
+    Min(usize),
+    Max(usize),
+    Call1(fn(f64) -> f64),
+    Call2(fn(f64, f64) -> f64),
+    /// Pops the given number of arguments and passes them as a slice.
+    CallN(usize, fn(&[f64]) -> f64),
+    /// Pops as many values as the shape has arguments, invalid parameters give NaN.
+    Shape(&'static Shape),
+}
+
+/// An expression compiled to a flat list of stack machine operations. Unlike the operator tree
+/// this can be evaluated on the audio thread, it neither allocates nor locks.
+#[derive(Debug, Clone)]
+pub struct Program {
+    ops: Vec<Op>,
+}
+
+impl Default for Program {
+    /// The identity function.
+    fn default() -> Self {
+        let mut program = Self::empty();
+        program.ops.push(Op::X);
+        program
+    }
+}
+
+impl Program {
+    /// Compiles the operator tree of `source`. The tree is expected to already evaluate
+    /// successfully with `context`. `x` and the macros are read when evaluating, everything else
+    /// is treated as a constant.
+    pub fn compile(
+        node: &Node,
+        source: &str,
+        context: &HashMapContext,
+    ) -> Result<Self, ShaperError> {
+        let mut compiler = Compiler {
+            source,
+            context,
+            ops: Vec::new(),
+            depth: 0,
+            max_depth: 0,
+        };
+        compiler.compile(node)?;
+
+        if compiler.max_depth > MAX_STACK {
+            return Err(ShaperError::Unsupported {
+                message: "Expression is nested too deeply for the exact engine".to_owned(),
+                span: Span::whole(source),
+            });
+        }
+        if compiler.ops.len() > MAX_OPS {
+            return Err(ShaperError::Unsupported {
+                message: "Expression is too long for the exact engine".to_owned(),
+                span: Span::whole(source),
+            });
+        }
+        let mut program = Self::empty();
+        program.ops.extend_from_slice(&compiler.ops);
+        Ok(program)
+    }
+
+    /// A program without any operations, used when an expression can't be compiled.
+    pub fn empty() -> Self {
+        Self {
+            ops: Vec::with_capacity(MAX_OPS),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Copies `source` into this program, reusing the existing allocation.
+    pub fn copy_from(&mut self, source: &Self) {
+        self.ops.clear();
+        self.ops.extend_from_slice(&source.ops);
+    }
+
+    pub fn eval(&self, x: f32, macros: &Macros) -> f32 {
+        let mut stack = [Number::float(0.0); MAX_STACK];
+        let mut top = 0;
+        macro_rules! pop {
+            () => {{
+                top -= 1;
+                stack[top]
+            }};
+        }
+        macro_rules! push {
+            ($value:expr) => {{
+                let value = $value;
+                stack[top] = value;
+                top += 1;
+            }};
+        }
+        macro_rules! binary {
+            (|$a:ident, $b:ident| $result:expr) => {{
+                let $b = pop!();
+                let $a = pop!();
+                push!($result);
+            }};
+        }
+
+        for op in self.ops.iter() {
+            match *op {
+                Op::Const(value) => push!(value),
+                Op::X => push!(Number::float(x as f64)),
+                Op::Macro(index) => push!(Number::float(macros[index] as f64)),
+                Op::Neg => {
+                    let a = pop!();
+                    push!(Number {
+                        value: -a.value,
+                        ..a
+                    });
+                }
+                Op::Abs => {
+                    let a = pop!();
+                    push!(Number {
+                        value: a.value.abs(),
+                        ..a
+                    });
+                }
+                Op::Add => binary!(|a, b| Number::arithmetic(a, b, a.value + b.value)),
+                Op::Sub => binary!(|a, b| Number::arithmetic(a, b, a.value - b.value)),
+                Op::Mul => binary!(|a, b| Number::arithmetic(a, b, a.value * b.value)),
+                Op::Div => binary!(|a, b| divide(a, b)),
+                Op::Mod => binary!(|a, b| remainder(a, b)),
+                Op::Pow => binary!(|a, b| Number::float(a.value.powf(b.value))),
+                Op::Eq => binary!(|a, b| Number::boolean(a.kind == b.kind && a.value == b.value)),
+                Op::Neq => binary!(|a, b| Number::boolean(a.kind != b.kind || a.value != b.value)),
+                Op::Gt => binary!(|a, b| Number::boolean(a.value > b.value)),
+                Op::Lt => binary!(|a, b| Number::boolean(a.value < b.value)),
+                Op::Geq => binary!(|a, b| Number::boolean(a.value >= b.value)),
+                Op::Leq => binary!(|a, b| Number::boolean(a.value <= b.value)),
+                Op::And => binary!(|a, b| Number::boolean(a.value != 0.0 && b.value != 0.0)),
+                Op::Or => binary!(|a, b| Number::boolean(a.value != 0.0 || b.value != 0.0)),
+                Op::Not => push!(Number::boolean(pop!().value == 0.0)),
+                Op::Select => {
+                    let otherwise = pop!();
+                    let then = pop!();
+                    let condition = pop!();
+                    push!(if condition.value != 0.0 {
+                        then
+                    } else {
+                        otherwise
+                    });
+                }
+                Op::Min(count) => {
+                    top -= count;
+                    push!(extremum(
+                        &stack[top..top + count],
+                        f64::min,
+                        i64::MAX as f64,
+                        f64::INFINITY
+                    ));
+                }
+                Op::Max(count) => {
+                    top -= count;
+                    push!(extremum(
+                        &stack[top..top + count],
+                        f64::max,
+                        i64::MIN as f64,
+                        f64::NEG_INFINITY
+                    ));
+                }
+                Op::Call1(function) => push!(Number::float(function(pop!().value))),
+                Op::Call2(function) => binary!(|a, b| Number::float(function(a.value, b.value))),
+                Op::CallN(count, function) => {
+                    top -= count;
+                    let arguments = values(&stack[top..top + count]);
+                    push!(Number::float(function(&arguments[..count])));
+                }
+                Op::Shape(shape) => {
+                    let count = shape.arguments();
+                    top -= count;
+                    let arguments = values(&stack[top..top + count]);
+                    push!(Number::float(
+                        shape.call(&arguments[..count]).unwrap_or(f64::NAN)
+                    ));
+                }
+            }
+        }
+
+        stack[0].value as f32
+    }
+}
+
+/// Divides like evalexpr, which truncates when dividing two integers. It fails on integer
+/// division by zero, which can only be NaN here.
+fn divide(a: Number, b: Number) -> Number {
+    if !(a.is_int() && b.is_int()) {
+        Number::float(a.value / b.value)
+    } else if b.value == 0.0 {
+        Number::float(f64::NAN)
+    } else {
+        Number::arithmetic(a, b, (a.value / b.value).trunc())
+    }
+}
+
+/// The remainder like evalexpr, see [`divide()`].
+fn remainder(a: Number, b: Number) -> Number {
+    if a.is_int() && b.is_int() && b.value == 0.0 {
+        Number::float(f64::NAN)
+    } else {
+        Number::arithmetic(a, b, a.value % b.value)
+    }
+}
+
+/// `min()` or `max()` like evalexpr, which finds the extremes of the integers and the floats
+/// separately. The integer is only returned if it's strictly beyond the float.
+fn extremum(
+    numbers: &[Number],
+    pick: fn(f64, f64) -> f64,
+    int_start: f64,
+    float_start: f64,
+) -> Number {
+    let (mut int, mut float) = (int_start, float_start);
+    for number in numbers {
+        if number.is_int() {
+            int = pick(int, number.value);
+        } else {
+            float = pick(float, number.value);
+        }
+    }
+
+    if int != float && pick(int, float) == int {
+        Number {
+            value: int,
+            kind: Kind::Int,
+        }
+    } else {
+        Number::float(float)
+    }
+}
+
+/// Copies the values of the arguments of a call.
+fn values(arguments: &[Number]) -> [f64; MAX_ARGUMENTS] {
+    let mut values = [0.0; MAX_ARGUMENTS];
+    for (value, argument) in values.iter_mut().zip(arguments) {
+        *value = argument.value;
+    }
+    values
+}
+
+// The orders are checked here instead of going through the functions used for the table, their
+// errors allocate. Invalid orders give NaN.
+
+fn cheb(x: f64, order: f64) -> f64 {
+    if is_valid_order(order) {
+        chebychev::first_kind(x, order)
+    } else {
+        f64::NAN
+    }
+}
+
+/// `order` rounded towards zero like evalexpr's integer conversion, if it's valid.
+fn whole_order(order: f64) -> Option<usize> {
+    let order = order.trunc();
+    is_valid_order(order).then_some(order as usize)
+}
+
+fn cheb_u(x: f64, order: f64) -> f64 {
+    whole_order(order).map_or(f64::NAN, |order| chebychev::second_kind(x, order))
+}
+
+fn legendre_op(x: f64, order: f64) -> f64 {
+    whole_order(order).map_or(f64::NAN, |order| legendre::polynomial(x, order))
+}
+
+fn hermite_op(x: f64, order: f64) -> f64 {
+    whole_order(order).map_or(f64::NAN, |order| hermite::polynomial(x, order))
+}
+
+fn laguerre_op(x: f64, order: f64) -> f64 {
+    whole_order(order).map_or(f64::NAN, |order| laguerre::polynomial(x, order))
+}
+
+fn bernstein_op(arguments: &[f64]) -> f64 {
+    let (index, order) = (arguments[1].trunc(), arguments[2].trunc());
+    if index >= 0.0 && (0.0..=MAX_BERNSTEIN_ORDER as f64).contains(&order) {
+        bernstein::basis(arguments[0], index as usize, order as usize)
+    } else {
+        f64::NAN
+    }
+}
+
+fn bezier_op(arguments: &[f64]) -> f64 {
+    bezier(
+        arguments[0],
+        [arguments[1], arguments[2], arguments[3], arguments[4]],
+    )
+}
+
+/// The functions the exact engine knows how to call, and the operation they compile to.
+fn function(identifier: &str) -> Option<(usize, Op)> {
+    let op = match identifier {
+        "math::ln" => Op::Call1(f64::ln),
+        "math::log" => Op::Call2(f64::log),
+        "math::log2" => Op::Call1(f64::log2),
+        "math::log10" => Op::Call1(f64::log10),
+        "math::exp" => Op::Call1(f64::exp),
+        "math::exp2" => Op::Call1(f64::exp2),
+        "math::pow" => Op::Call2(f64::powf),
+        "math::cos" => Op::Call1(f64::cos),
+        "math::acos" => Op::Call1(f64::acos),
+        "math::cosh" => Op::Call1(f64::cosh),
+        "math::acosh" => Op::Call1(f64::acosh),
+        "math::sin" => Op::Call1(f64::sin),
+        "math::asin" => Op::Call1(f64::asin),
+        "math::sinh" => Op::Call1(f64::sinh),
+        "math::asinh" => Op::Call1(f64::asinh),
+        "math::tan" => Op::Call1(f64::tan),
+        "math::atan" => Op::Call1(f64::atan),
+        "math::tanh" => Op::Call1(f64::tanh),
+        "math::atanh" => Op::Call1(f64::atanh),
+        "math::atan2" => Op::Call2(f64::atan2),
+        "math::sqrt" => Op::Call1(f64::sqrt),
+        "math::cbrt" => Op::Call1(f64::cbrt),
+        "math::hypot" => Op::Call2(f64::hypot),
+        "math::abs" => Op::Abs,
+        "floor" => Op::Call1(f64::floor),
+        "round" => Op::Call1(f64::round),
+        "ceil" => Op::Call1(f64::ceil),
+        "if" => Op::Select,
+        "Cheb" => Op::Call2(cheb),
+        "ChebU" => Op::Call2(cheb_u),
+        "Legendre" => Op::Call2(legendre_op),
+        "Hermite" => Op::Call2(hermite_op),
+        "Laguerre" => Op::Call2(laguerre_op),
+        "Bernstein" => Op::CallN(3, bernstein_op),
+        "Bezier" => Op::CallN(5, bezier_op),
+        identifier => {
+            let shape = shapes::find(identifier)?;
+            return Some((shape.arguments(), Op::Shape(shape)));
+        }
+    };
+    let arguments = match op {
+        Op::Call1(_) | Op::Abs => 1,
+        Op::Call2(_) => 2,
+        Op::Select => 3,
+        Op::CallN(count, _) => count,
+        _ => unreachable!(),
+    };
+    Some((arguments, op))
+}
+
+/// Whether `identifier` can change between evaluations of the same program.
+fn is_variable(identifier: &str) -> bool {
+    identifier == "x" || MACRO_NAMES.contains(&identifier)
+}
+
+/// Whether `node` or any of its children reads `x` or a macro. evalexpr's iterators skip the node
+/// itself.
+fn reads_variables(node: &Node) -> bool {
+    matches!(node.operator(), Operator::VariableIdentifierRead { identifier } if is_variable(identifier))
+        || node.iter_read_variable_identifiers().any(is_variable)
+}
+
+struct Compiler<'a> {
+    source: &'a str,
+    context: &'a HashMapContext,
+    ops: Vec<Op>,
+    depth: usize,
+    max_depth: usize,
+}
+
+impl Compiler<'_> {
+    fn compile(&mut self, node: &Node) -> Result<(), ShaperError> {
+        // Anything that doesn't depend on `x` or the macros is evaluated right away by evalexpr
+        // itself. Apart from being faster this keeps its integer semantics exactly.
+        if !reads_variables(node) {
+            let value = node
+                .eval_with_context(self.context)
+                .map_err(|err| self.unsupported(err.to_string(), Span::whole(self.source)))?;
+            return self.constant(&value);
+        }
+
+        match node.operator() {
+            Operator::RootNode => match node.children() {
+                [child] => self.compile(child),
+                _ => Err(self.unsupported("Empty parentheses", Span::whole(self.source))),
+            },
+            Operator::VariableIdentifierRead { identifier } if identifier == "x" => {
+                self.emit(Op::X, 0);
+                Ok(())
+            }
+            Operator::VariableIdentifierRead { identifier } if is_variable(identifier) => {
+                let index = MACRO_NAMES
+                    .iter()
+                    .position(|name| name == identifier)
+                    .expect("Not a macro");
+                self.emit(Op::Macro(index), 0);
+                Ok(())
+            }
+            Operator::VariableIdentifierRead { identifier } => {
+                match self.context.get_value(identifier) {
+                    Some(value) => self.constant(&value.clone()),
+                    None => Err(ShaperError::UnknownIdentifier {
+                        identifier: identifier.clone(),
+                        span: self.span(identifier),
+                    }),
+                }
+            }
+            Operator::FunctionIdentifier { identifier } => {
+                let arguments = arguments(node);
+                for argument in arguments {
+                    self.compile(argument)?;
+                }
+
+                match identifier.as_str() {
+                    "min" => self.emit(Op::Min(arguments.len()), arguments.len()),
+                    "max" => self.emit(Op::Max(arguments.len()), arguments.len()),
+                    _ => match function(identifier) {
+                        Some((count, op)) if count == arguments.len() => self.emit(op, count),
+                        Some((count, _)) => {
+                            return Err(ShaperError::ArgumentCount {
+                                function: identifier.clone(),
+                                expected: count..=count,
+                                actual: arguments.len(),
+                                span: self.span(identifier),
+                            })
+                        }
+                        None => {
+                            return Err(self.unsupported(
+                                format!("'{identifier}' is not supported by the exact engine"),
+                                self.span(identifier),
+                            ))
+                        }
+                    },
+                }
+                Ok(())
+            }
+            operator => {
+                let op = match operator {
+                    Operator::Neg => Op::Neg,
+                    Operator::Not => Op::Not,
+                    Operator::Add => Op::Add,
+                    Operator::Sub => Op::Sub,
+                    Operator::Mul => Op::Mul,
+                    Operator::Div => Op::Div,
+                    Operator::Mod => Op::Mod,
+                    Operator::Exp => Op::Pow,
+                    Operator::Eq => Op::Eq,
+                    Operator::Neq => Op::Neq,
+                    Operator::Gt => Op::Gt,
+                    Operator::Lt => Op::Lt,
+                    Operator::Geq => Op::Geq,
+                    Operator::Leq => Op::Leq,
+                    Operator::And => Op::And,
+                    Operator::Or => Op::Or,
+                    operator => {
+                        return Err(self.unsupported(
+                            format!("'{operator}' is not supported by the exact engine"),
+                            Span::whole(self.source),
+                        ))
+                    }
+                };
+                for child in node.children() {
+                    self.compile(child)?;
+                }
+                self.emit(op, node.children().len());
+                Ok(())
+            }
+        }
+    }
+
+    fn constant(&mut self, value: &Value) -> Result<(), ShaperError> {
+        let number = match value {
+            Value::Float(value) => Number::float(*value),
+            Value::Int(value) => Number {
+                value: *value as f64,
+                kind: Kind::Int,
+            },
+            Value::Boolean(value) => Number::boolean(*value),
+            value => {
+                return Err(
+                    self.unsupported(format!("{value} is not a number"), Span::whole(self.source))
+                )
+            }
+        };
+        self.emit(Op::Const(number), 0);
+        Ok(())
+    }
+
+    /// Appends `op`, which pops `arguments` values and pushes its result.
+    fn emit(&mut self, op: Op, arguments: usize) {
+        self.ops.push(op);
+        self.depth = self.depth + 1 - arguments;
+        self.max_depth = self.max_depth.max(self.depth);
+    }
+
+    fn span(&self, identifier: &str) -> Span {
+        Span::identifier(self.source, identifier, 0).unwrap_or_else(|| Span::whole(self.source))
+    }
+
+    fn unsupported(&self, message: impl Into<String>, span: Span) -> ShaperError {
+        ShaperError::Unsupported {
+            message: message.into(),
+            span,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use evalexpr::{build_operator_tree, ContextWithMutableVariables};
+
+    use super::*;
+    use crate::shaper::Shaper;
+
+    const MACROS: Macros = [0.25, 0.5, 0.75, 1.0];
+
+    fn context() -> HashMapContext {
+        let mut context = Shaper::default_context();
+        for (name, value) in MACRO_NAMES.iter().zip(MACROS) {
+            context
+                .set_value(name.to_string(), Value::Float(value as f64))
+                .unwrap();
+        }
+        context
+    }
+
+    fn compile(expression: &str) -> Program {
+        let node = build_operator_tree(expression).unwrap();
+        Program::compile(&node, expression, &context()).unwrap()
+    }
+
+    /// Checks that the compiled `expression` gives exactly what evalexpr gives for the table.
+    fn assert_matches_evalexpr(expression: &str) {
+        let node = build_operator_tree(expression).unwrap();
+        let mut context = context();
+        let program = Program::compile(&node, expression, &context).unwrap();
+        for i in -8..=8 {
+            let x = i as f32 / 4.0;
+            context
+                .set_value("x".to_owned(), Value::Float(x as f64))
+                .unwrap();
+            let expected = node.eval_number_with_context(&context).unwrap() as f32;
+            let actual = program.eval(x, &MACROS);
+            assert!(
+                expected == actual || (expected.is_nan() && actual.is_nan()),
+                "{expression} at x = {x}: evalexpr gives {expected}, the program {actual}"
+            );
+        }
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_matches_evalexpr("x");
+        assert_matches_evalexpr("-x * 2 + 1 - x / 3");
+        assert_matches_evalexpr("x ^ 3 - 0.5 * x ^ 2");
+        assert_matches_evalexpr("x % 0.3");
+        assert_matches_evalexpr("a * x + b - c / d");
+    }
+
+    #[test]
+    fn integer_semantics() {
+        assert_matches_evalexpr("if(x > 0, 1, 2) / 2");
+        assert_matches_evalexpr("if(x > 0, 7, -7) / 2 * x");
+        assert_matches_evalexpr("if(x > 0, 7, -7) % 3");
+        assert_matches_evalexpr("(if(x > 0, 3, 4) + 1) / 2");
+        assert_matches_evalexpr("math::abs(if(x > 0, -3, 5)) / 2");
+        assert_matches_evalexpr("min(x, 1) / 2");
+        assert_matches_evalexpr("max(if(x > 0, 1, 2), x) / 2");
+        assert_matches_evalexpr("if(if(x > 0, 1, 2) == 1, x, -x)");
+        assert_matches_evalexpr("if(if(x > 0, 1, 2) == 1.0, x, -x)");
+        assert_matches_evalexpr("if(x > 0, 2, 3) ^ 2 / 2");
+    }
+
+    #[test]
+    fn logic() {
+        assert_matches_evalexpr("if(x > 0 && x < 1, x, 0)");
+        assert_matches_evalexpr("if(!(x >= 0) || x == 1, 1, 0)");
+        assert_matches_evalexpr("if(x != 0.5, x, -1)");
+    }
+
+    #[test]
+    fn functions() {
+        assert_matches_evalexpr("math::tanh(3 * x) + math::sin(PI * x)");
+        assert_matches_evalexpr("math::atan2(x, 0.5) * math::hypot(x, a)");
+        assert_matches_evalexpr("floor(4 * x) / 4 + round(x) - ceil(x)");
+        assert_matches_evalexpr("Cheb(x, 3) + Cheb(x, 2.5) + ChebU(x, 4)");
+        assert_matches_evalexpr("Legendre(x, 5) + Hermite(x, 3) + Laguerre(x, 2)");
+        assert_matches_evalexpr("Bernstein(x, 1, 3) + Bezier(x, 0, 0.2, 0.8, 1)");
+        assert_matches_evalexpr("softclip(x, 2) + fold(x, 0.5) + clamp(x, -b, c)");
+        assert_matches_evalexpr("tanhpade(2 * x) * sinc(x)");
+    }
+
+    #[test]
+    fn invalid_orders_give_nan() {
+        assert!(compile("Cheb(x, x - 2)").eval(0.5, &MACROS).is_nan());
+        assert!(compile("Cheb(x, x / 0)").eval(0.0, &MACROS).is_nan());
+        assert!(compile("Cheb(x, -a)").eval(0.5, &MACROS).is_nan());
+        assert!(compile("Cheb(x, a * 8000)").eval(0.5, &MACROS).is_nan());
+        assert!(compile("Legendre(x, -a * 8)").eval(0.5, &MACROS).is_nan());
+        assert!(compile("Bernstein(x, 1, d * 100)")
+            .eval(0.5, &MACROS)
+            .is_nan());
+        assert!(compile("softclip(x, -a)").eval(0.5, &MACROS).is_nan());
+    }
+
+    #[test]
+    fn unsupported() {
+        let node = build_operator_tree("x; x").unwrap();
+        assert!(Program::compile(&node, "x; x", &context()).is_err());
+        let node = build_operator_tree("typeof(x)").unwrap();
+        assert!(Program::compile(&node, "typeof(x)", &context()).is_err());
+    }
+}
@@ -0,0 +1,1051 @@
+use std::ops::Range;
+
+use super::syntax::preprocess;
+use super::{CONSTANT_NAMES, MACRO_NAMES};
+use crate::error::{ShaperError, Span};
+
+/// Expansions that grow beyond this many bytes are rejected, nesting functions that use their
+/// argument several times makes the expression grow exponentially.
+const MAX_EXPANDED_LEN: usize = 1 << 20;
+
+/// Text along with the position in the original source every byte came from, so errors found in
+/// generated text can point at what the user wrote.
+#[derive(Debug, Clone, Default)]
+pub struct Mapped {
+    text: String,
+    origins: Vec<usize>,
+}
+
+impl Mapped {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The position in the source the byte at `index` came from.
+    pub fn origin(&self, index: usize) -> usize {
+        match self.origins.get(index) {
+            Some(&origin) => origin,
+            None => self.origins.last().map_or(0, |origin| origin + 1),
+        }
+    }
+
+    /// Appends `text` that doesn't appear in the source, attributed to the source position
+    /// `origin`.
+    pub fn push_str(&mut self, text: &str, origin: usize) {
+        self.text.push_str(text);
+        self.origins
+            .extend(std::iter::repeat(origin).take(text.len()));
+    }
+
+    /// Appends `range` of `source`.
+    pub fn push_source(&mut self, source: &str, range: Range<usize>) {
+        self.text.push_str(&source[range.clone()]);
+        self.origins.extend(range);
+    }
+
+    pub fn append(&mut self, other: &Mapped) {
+        self.text.push_str(&other.text);
+        self.origins.extend_from_slice(&other.origins);
+    }
+
+    pub fn slice(&self, range: Range<usize>) -> Mapped {
+        Mapped {
+            text: self.text[range.clone()].to_owned(),
+            origins: self.origins[range].to_owned(),
+        }
+    }
+
+    /// Maps `span`, which refers to this text, back to `source`.
+    pub fn map_span(&self, span: &Span, source: &str) -> Span {
+        let start = self.origin(span.range.start);
+        let end = if span.range.end > span.range.start {
+            (self.origin(span.range.end - 1) + 1).max(start)
+        } else {
+            start
+        };
+        Span::new(source, start.min(source.len())..end.min(source.len()))
+    }
+
+    /// Maps the span of `error`, which refers to this text, back to `source`.
+    pub fn map_error(&self, error: ShaperError, source: &str) -> ShaperError {
+        error.map_span(|span| self.map_span(span, source))
+    }
+}
+
+/// A piece of a definition's body.
+#[derive(Debug, Clone)]
+enum Segment {
+    Text(Mapped),
+    /// Replaced by the argument with this index when the function gets called.
+    Parameter(usize),
+}
+
+#[derive(Debug)]
+struct Definition {
+    name: String,
+    parameters: Vec<String>,
+    /// Whether the body takes `x` as an additional, last parameter. Uses pass on whichever `x`
+    /// they see, so a definition evaluated at some other input than the current one sees that
+    /// input.
+    implicit_x: bool,
+    body: Vec<Segment>,
+}
+
+/// One part of a piecewise curve, covering inputs from `start` to `end`.
+struct Piece {
+    start: f64,
+    end: f64,
+    /// The position of the interval in the stripped source.
+    origin: usize,
+    /// Takes `x` as its only parameter.
+    body: Vec<Segment>,
+}
+
+/// Expands a script into a single expression. Scripts can contain `#` comments, variables
+/// defined with `let name = value` and functions defined with `name(a, b) = body`, optionally
+/// also prefixed with `let`. Definitions have to start on their own line and end with it, unless
+/// parentheses are still open. They can use everything defined before them, and all of them have
+/// to come before the expression that defines the curve. Every use of a definition is replaced by
+/// its body in parentheses, with the arguments substituted for the parameters.
+///
+/// Instead of a single expression, the curve can also be given in pieces, one per line, like
+/// `x < -0.5: -0.5`, `-0.5..0.5: x` and `> 0.5: 0.5`. The pieces have to meet without gaps or
+/// overlaps, and the outermost ones extend to all inputs below or above them. With
+/// `continuous` set, every piece is shifted so it meets its neighbor towards zero at their
+/// shared boundary, while the piece around zero stays as it is.
+///
+/// The bodies and the curve expression go through [`preprocess()`] first, so they can use the
+/// more common math notation described there.
+pub fn expand(source: &str, continuous: bool) -> Result<Mapped, ShaperError> {
+    let stripped = strip_comments(source);
+    let parse_error = |message: &str, range: Range<usize>| ShaperError::Parse {
+        message: message.to_owned(),
+        span: stripped.map_span(&Span::new(stripped.text(), range), source),
+    };
+
+    let mut definitions: Vec<Definition> = Vec::new();
+    let mut expression = Mapped::default();
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    while start < stripped.text().len() {
+        let end = statement_end(stripped.text(), start);
+        let statement = stripped.slice(start..end);
+        let Some(header) = Header::parse(statement.text()) else {
+            match parse_piece(statement.text()) {
+                Some(piece) => {
+                    let (interval, body_start) = piece.map_err(|(message, range)| {
+                        parse_error(message, start + range.start..start + range.end)
+                    })?;
+                    let body_end = statement.text().trim_end().len();
+                    let body = statement.slice(body_start.min(body_end)..body_end);
+                    if body.text().trim().is_empty() {
+                        return Err(parse_error(
+                            "The piece is missing its expression",
+                            start..start + body_start,
+                        ));
+                    }
+                    let origin = start + skip_whitespace(statement.text(), 0);
+                    pieces.push((interval, origin, body));
+                }
+                None => expression.append(&statement),
+            }
+            start = end;
+            continue;
+        };
+        let header = header.map_err(|(message, range)| {
+            parse_error(message, start + range.start..start + range.end)
+        })?;
+
+        if !expression.text().trim().is_empty() || !pieces.is_empty() {
+            return Err(parse_error(
+                "Definitions have to come before the curve expression",
+                start + header.name.start..start + header.name.end,
+            ));
+        }
+        let name = &statement.text()[header.name.clone()];
+        if name == "x" || MACRO_NAMES.contains(&name) {
+            return Err(parse_error(
+                "The input and the macros can't be redefined",
+                start + header.name.start..start + header.name.end,
+            ));
+        }
+        if definitions.iter().any(|definition| definition.name == name) {
+            return Err(parse_error(
+                "This name is already defined",
+                start + header.name.start..start + header.name.end,
+            ));
+        }
+        let parameters: Vec<String> = header
+            .parameters
+            .iter()
+            .map(|range| statement.text()[range.clone()].to_owned())
+            .collect();
+        if let Some((i, _)) = parameters
+            .iter()
+            .enumerate()
+            .find(|(i, parameter)| parameters[..*i].contains(*parameter))
+        {
+            let range = &header.parameters[i];
+            return Err(parse_error(
+                "Duplicate parameter name",
+                start + range.start..start + range.end,
+            ));
+        }
+
+        let body_end = statement.text().trim_end().len();
+        let body_start = skip_whitespace(statement.text(), header.body_start).min(body_end);
+        let body = statement.slice(body_start..body_end);
+        if body.text().is_empty() {
+            return Err(parse_error(
+                "The definition is missing its body",
+                start + header.name.start..start + header.name.end,
+            ));
+        }
+        let implicit_x = !parameters.iter().any(|parameter| parameter == "x");
+        let mut scope = parameters.clone();
+        if implicit_x {
+            scope.push("x".to_owned());
+        }
+        let body = normalize_syntax(&body, &definitions, &scope);
+        let body = expand_text(&body, &definitions, &scope, source)?;
+        definitions.push(Definition {
+            name: name.to_owned(),
+            parameters,
+            implicit_x,
+            body,
+        });
+        start = end;
+    }
+
+    let expression_start = expression.text().len() - expression.text().trim_start().len();
+    if !pieces.is_empty() {
+        if expression_start < expression.text().len() {
+            return Err(ShaperError::Parse {
+                message: "A piecewise curve can only consist of pieces".to_owned(),
+                span: expression.map_span(
+                    &Span::new(
+                        expression.text(),
+                        char_range(expression.text(), expression_start),
+                    ),
+                    source,
+                ),
+            });
+        }
+
+        let x = ["x".to_owned()];
+        let mut compiled = Vec::new();
+        for ((start, end), origin, body) in pieces {
+            let body = normalize_syntax(&body, &definitions, &x);
+            compiled.push(Piece {
+                start,
+                end,
+                origin: stripped.origin(origin),
+                body: expand_text(&body, &definitions, &x, source)?,
+            });
+        }
+        compiled.sort_by(|a, b| a.start.total_cmp(&b.start));
+        if let Some(piece) = compiled
+            .windows(2)
+            .find(|pair| pair[0].end != pair[1].start)
+            .map(|pair| &pair[1])
+        {
+            return Err(ShaperError::Parse {
+                message: "The pieces have to meet without gaps or overlaps".to_owned(),
+                span: Span::new(source, char_range(source, piece.origin)),
+            });
+        }
+        return Ok(lower_pieces(&compiled, continuous));
+    }
+
+    if expression_start == expression.text().len() && !definitions.is_empty() {
+        return Err(ShaperError::Parse {
+            message: "The script needs an expression for the curve after its definitions"
+                .to_owned(),
+            span: Span::whole(source),
+        });
+    }
+    let expression = normalize_syntax(&expression, &definitions, &[]);
+    Ok(flatten(expand_text(
+        &expression,
+        &definitions,
+        &[],
+        source,
+    )?))
+}
+
+/// Runs the syntax preprocessor on `text`, which can use `definitions` and `parameters`.
+fn normalize_syntax(text: &Mapped, definitions: &[Definition], parameters: &[String]) -> Mapped {
+    let is_parameter = |name: &str| parameters.iter().any(|parameter| parameter == name);
+    let is_variable = |name: &str| {
+        name == "x"
+            || MACRO_NAMES.contains(&name)
+            || CONSTANT_NAMES.contains(&name)
+            || is_parameter(name)
+            || definitions
+                .iter()
+                .any(|definition| definition.name == name && definition.parameters.is_empty())
+    };
+    let is_defined = |name: &str| {
+        is_parameter(name) || definitions.iter().any(|definition| definition.name == name)
+    };
+    preprocess(text, &is_variable, &is_defined)
+}
+
+/// Parses the interval in front of a piece's body. Returns `None` if `statement` is not a piece,
+/// otherwise the interval and the start of the body, or an error message and the range it
+/// refers to.
+#[allow(clippy::type_complexity)]
+fn parse_piece(
+    statement: &str,
+) -> Option<Result<((f64, f64), usize), (&'static str, Range<usize>)>> {
+    // Colons only ever appear in pieces and in paths like `math::sin`
+    let mut colon = None;
+    let mut i = 0;
+    while i < statement.len() {
+        if statement[i..].starts_with('"') {
+            i = string_end(statement, i);
+            continue;
+        }
+        if statement[i..].starts_with("::") {
+            i += 2;
+            continue;
+        }
+        if statement[i..].starts_with(':') {
+            colon = Some(i);
+            break;
+        }
+        i += statement[i..].chars().next().map_or(1, char::len_utf8);
+    }
+    let colon = colon?;
+
+    let interval_start = skip_whitespace(statement, 0);
+    let range = interval_start..colon.max(interval_start + 1);
+    let error = (
+        "Expected an interval like '< 0', '-1..1' or '> 0'",
+        range.clone(),
+    );
+    let compact: String = statement[..colon]
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    let compact = compact.replace("<=", "<").replace(">=", ">");
+    let number = |text: &str| text.parse::<f64>().ok().filter(|value| value.is_finite());
+
+    let interval = if let Some((start, end)) = compact.split_once("..") {
+        number(start).zip(number(end))
+    } else {
+        match compact.split('<').collect::<Vec<_>>()[..] {
+            ["" | "x", end] => number(end).map(|end| (f64::NEG_INFINITY, end)),
+            [start, "x", end] => number(start).zip(number(end)),
+            _ => match compact.split('>').collect::<Vec<_>>()[..] {
+                ["" | "x", start] => number(start).map(|start| (start, f64::INFINITY)),
+                [end, "x", start] => number(start).zip(number(end)),
+                _ => None,
+            },
+        }
+    };
+    Some(match interval {
+        Some((start, end)) if start < end => Ok(((start, end), colon + 1)),
+        Some(_) => Err(("The interval is empty", range)),
+        None => Err(error),
+    })
+}
+
+/// Turns `pieces`, which are sorted and meet at their boundaries, into nested `if` calls.
+fn lower_pieces(pieces: &[Piece], continuous: bool) -> Mapped {
+    let anchor = pieces
+        .iter()
+        .position(|piece| 0.0 < piece.end)
+        .unwrap_or(pieces.len() - 1);
+    let mut x = Mapped::default();
+    x.push_str("x", pieces[0].origin);
+    let x = [Segment::Text(x)];
+
+    let mut result = Builder::default();
+    for (i, piece) in pieces.iter().enumerate() {
+        if i + 1 < pieces.len() {
+            result.push_str("if(x < ", piece.origin);
+            result.push_str(&constant(piece.end), piece.origin);
+            result.push_str(", ", piece.origin);
+            piece_at(&mut result, pieces, i, anchor, continuous, &x);
+            result.push_str(", ", piece.origin);
+        } else {
+            piece_at(&mut result, pieces, i, anchor, continuous, &x);
+        }
+    }
+    for piece in &pieces[..pieces.len() - 1] {
+        result.push_str(")", piece.origin);
+    }
+    flatten(result.finish())
+}
+
+/// Appends piece `index` evaluated at `argument`. With `continuous` set it is offset so it
+/// matches its neighbor towards `anchor` at their boundary, which in turn is offset the same way.
+fn piece_at(
+    out: &mut Builder,
+    pieces: &[Piece],
+    index: usize,
+    anchor: usize,
+    continuous: bool,
+    argument: &[Segment],
+) {
+    let piece = &pieces[index];
+    if !continuous || index == anchor {
+        out.substitute(&piece.body, &[argument.to_vec()], piece.origin);
+        return;
+    }
+
+    let (neighbor, boundary) = if index > anchor {
+        (index - 1, piece.start)
+    } else {
+        (index + 1, piece.end)
+    };
+    let mut boundary_text = Mapped::default();
+    boundary_text.push_str(&constant(boundary), piece.origin);
+    let boundary = [Segment::Text(boundary_text)];
+
+    out.push_str("(", piece.origin);
+    out.substitute(&piece.body, &[argument.to_vec()], piece.origin);
+    out.push_str(" - ", piece.origin);
+    out.substitute(&piece.body, &[boundary.to_vec()], piece.origin);
+    out.push_str(" + ", piece.origin);
+    piece_at(out, pieces, neighbor, anchor, continuous, &boundary);
+    out.push_str(")", piece.origin);
+}
+
+/// Formats `value` so evalexpr reads it as a float.
+fn constant(value: f64) -> String {
+    let text = value.to_string();
+    if text.contains('.') {
+        format!("({text})")
+    } else {
+        format!("({text}.0)")
+    }
+}
+
+/// Replaces comments by nothing, keeping the line breaks.
+fn strip_comments(source: &str) -> Mapped {
+    let mut stripped = Mapped::default();
+    let mut in_string = false;
+    let mut chars = source.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => in_string = !in_string,
+            '\\' if in_string => {
+                // Keep escaped quotes from ending the string
+                let end = chars.next().map_or(i + 1, |(j, c)| j + c.len_utf8());
+                stripped.push_source(source, i..end);
+                continue;
+            }
+            '#' if !in_string => {
+                while chars.next_if(|&(_, c)| c != '\n').is_some() {}
+                continue;
+            }
+            _ => (),
+        }
+        stripped.push_source(source, i..i + c.len_utf8());
+    }
+    stripped
+}
+
+/// The end of the statement starting at `start`, including the line break. A statement ends
+/// with its line unless parentheses are still open.
+fn statement_end(text: &str, start: usize) -> usize {
+    let mut depth = 0i32;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in text[start..].char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '(' if !in_string => depth += 1,
+            ')' if !in_string => depth -= 1,
+            '\n' if depth <= 0 => return start + i + 1,
+            _ => (),
+        }
+    }
+    text.len()
+}
+
+/// The left hand side of a definition. All ranges are relative to the statement.
+struct Header {
+    name: Range<usize>,
+    parameters: Vec<Range<usize>>,
+    body_start: usize,
+}
+
+impl Header {
+    /// Returns `None` if `statement` is not a definition, or an error message and the range it
+    /// refers to if it's a malformed one.
+    fn parse(statement: &str) -> Option<Result<Self, (&'static str, Range<usize>)>> {
+        let mut position = skip_whitespace(statement, 0);
+        let has_let = statement[position..].starts_with("let")
+            && statement[position + 3..]
+                .chars()
+                .next()
+                .map_or(true, char::is_whitespace);
+        if has_let {
+            position = skip_whitespace(statement, position + 3);
+        }
+
+        match Self::parse_after_let(statement, position) {
+            // Without `let` only functions are definitions, `name = value` is an evalexpr
+            // assignment
+            Ok(header) if has_let || !header.parameters.is_empty() => Some(Ok(header)),
+            Err(error) if has_let => Some(Err(error)),
+            _ => None,
+        }
+    }
+
+    fn parse_after_let(
+        statement: &str,
+        start: usize,
+    ) -> Result<Self, (&'static str, Range<usize>)> {
+        let name_end = identifier_end(statement, start);
+        if name_end == start {
+            return Err(("Expected a name", char_range(statement, start)));
+        }
+        let name = start..name_end;
+
+        let mut position = skip_whitespace(statement, name_end);
+        let mut parameters = Vec::new();
+        if statement[position..].starts_with('(') {
+            position = skip_whitespace(statement, position + 1);
+            loop {
+                let end = identifier_end(statement, position);
+                if end == position {
+                    return Err(("Expected a parameter name", char_range(statement, position)));
+                }
+                parameters.push(position..end);
+                position = skip_whitespace(statement, end);
+                match statement[position..].chars().next() {
+                    Some(',') => position = skip_whitespace(statement, position + 1),
+                    Some(')') => {
+                        position = skip_whitespace(statement, position + 1);
+                        break;
+                    }
+                    _ => return Err(("Expected ',' or ')'", char_range(statement, position))),
+                }
+            }
+        }
+
+        if !statement[position..].starts_with('=') || statement[position..].starts_with("==") {
+            return Err(("Expected '='", char_range(statement, position)));
+        }
+        Ok(Header {
+            name,
+            parameters,
+            body_start: position + 1,
+        })
+    }
+}
+
+/// The range of the character at `start`, so errors can point at it without splitting it.
+fn char_range(text: &str, start: usize) -> Range<usize> {
+    let len = text[start..].chars().next().map_or(1, char::len_utf8);
+    start..start + len
+}
+
+fn skip_whitespace(text: &str, start: usize) -> usize {
+    text[start..]
+        .find(|c: char| !c.is_whitespace())
+        .map_or(text.len(), |i| start + i)
+}
+
+pub(super) fn is_identifier_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+pub(super) fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == ':' || c == '.'
+}
+
+/// The end of the identifier starting at `start`, or `start` if there is none.
+fn identifier_end(text: &str, start: usize) -> usize {
+    if !text[start..].starts_with(is_identifier_start) {
+        return start;
+    }
+    text[start..]
+        .find(|c: char| !is_identifier_char(c))
+        .map_or(text.len(), |i| start + i)
+}
+
+/// The end of the string literal starting at `start`, after the closing quote.
+pub(super) fn string_end(text: &str, start: usize) -> usize {
+    let mut escaped = false;
+    for (i, c) in text[start + 1..].char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return start + 1 + i + 1,
+            _ => (),
+        }
+    }
+    text.len()
+}
+
+/// The position of the parenthesis closing the one at `open`.
+fn closing_parenthesis(text: &str, open: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = open;
+    while i < text.len() {
+        let c = text[i..].chars().next()?;
+        match c {
+            '"' => {
+                i = string_end(text, i);
+                continue;
+            }
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => (),
+        }
+        i += c.len_utf8();
+    }
+    None
+}
+
+/// Splits the arguments between the parentheses of a call at the commas that aren't nested any
+/// deeper.
+fn split_arguments(text: &Mapped, range: Range<usize>) -> Vec<Mapped> {
+    let mut arguments = Vec::new();
+    let mut depth = 0;
+    let mut start = range.start;
+    let mut i = range.start;
+    while let Some(c) = text.text()[i..range.end].chars().next() {
+        match c {
+            '"' => {
+                i = string_end(text.text(), i);
+                continue;
+            }
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                arguments.push(text.slice(start..i));
+                start = i + 1;
+            }
+            _ => (),
+        }
+        i += c.len_utf8();
+    }
+    if !text.text()[start..range.end].trim().is_empty() || !arguments.is_empty() {
+        arguments.push(text.slice(start..range.end));
+    }
+    arguments
+}
+
+/// Collects segments, merging adjacent text.
+#[derive(Default)]
+struct Builder {
+    segments: Vec<Segment>,
+    text: Mapped,
+    /// The length of all text so far.
+    len: usize,
+}
+
+impl Builder {
+    fn push_str(&mut self, text: &str, origin: usize) {
+        self.text.push_str(text, origin);
+        self.len += text.len();
+    }
+
+    fn append(&mut self, text: &Mapped) {
+        self.text.append(text);
+        self.len += text.text().len();
+    }
+
+    fn push_parameter(&mut self, index: usize) {
+        self.segments
+            .push(Segment::Text(std::mem::take(&mut self.text)));
+        self.segments.push(Segment::Parameter(index));
+    }
+
+    /// Appends `body` in parentheses, with `arguments` substituted for its parameters.
+    fn substitute(&mut self, body: &[Segment], arguments: &[Vec<Segment>], origin: usize) {
+        self.push_str("(", origin);
+        for segment in body {
+            match segment {
+                Segment::Text(text) => self.append(text),
+                Segment::Parameter(index) => {
+                    self.push_str("(", origin);
+                    for segment in &arguments[*index] {
+                        match segment {
+                            Segment::Text(text) => self.append(text),
+                            Segment::Parameter(index) => self.push_parameter(*index),
+                        }
+                    }
+                    self.push_str(")", origin);
+                }
+            }
+        }
+        self.push_str(")", origin);
+    }
+
+    fn finish(mut self) -> Vec<Segment> {
+        self.segments.push(Segment::Text(self.text));
+        self.segments
+    }
+}
+
+/// Replaces the uses of `definitions` in `text`, and turns `parameters` into
+/// [`Segment::Parameter`]s.
+fn expand_text(
+    text: &Mapped,
+    definitions: &[Definition],
+    parameters: &[String],
+    source: &str,
+) -> Result<Vec<Segment>, ShaperError> {
+    let mut out = Builder::default();
+    let raw = text.text();
+    let mut i = 0;
+    while i < raw.len() {
+        let c = raw[i..].chars().next().expect("Not at a char boundary");
+        if c == '"' {
+            let end = string_end(raw, i);
+            out.append(&text.slice(i..end));
+            i = end;
+            continue;
+        }
+        let starts_identifier =
+            is_identifier_start(c) && !raw[..i].ends_with(|c: char| is_identifier_char(c));
+        if !starts_identifier {
+            out.append(&text.slice(i..i + c.len_utf8()));
+            i += c.len_utf8();
+            continue;
+        }
+
+        let end = identifier_end(raw, i);
+        let name = &raw[i..end];
+        let origin = text.origin(i);
+        let name_range = i..end;
+        let name_span = || text.map_span(&Span::new(raw, name_range.clone()), source);
+        if let Some(index) = parameters.iter().position(|parameter| parameter == name) {
+            out.push_parameter(index);
+            i = end;
+            continue;
+        }
+        let Some(definition) = definitions
+            .iter()
+            .find(|definition| definition.name == name)
+        else {
+            out.append(&text.slice(i..end));
+            i = end;
+            continue;
+        };
+
+        let mut arguments = Vec::new();
+        i = end;
+        if !definition.parameters.is_empty() {
+            let open = skip_whitespace(raw, end);
+            if !raw[open..].starts_with('(') {
+                return Err(ShaperError::ArgumentCount {
+                    function: name.to_owned(),
+                    expected: definition.parameters.len()..=definition.parameters.len(),
+                    actual: 0,
+                    span: name_span(),
+                });
+            }
+            let Some(close) = closing_parenthesis(raw, open) else {
+                return Err(ShaperError::Parse {
+                    message: "Unclosed opening parenthesis".to_owned(),
+                    span: text.map_span(&Span::new(raw, open..open + 1), source),
+                });
+            };
+            for argument in split_arguments(text, open + 1..close) {
+                arguments.push(expand_text(&argument, definitions, parameters, source)?);
+            }
+            if arguments.len() != definition.parameters.len() {
+                return Err(ShaperError::ArgumentCount {
+                    function: name.to_owned(),
+                    expected: definition.parameters.len()..=definition.parameters.len(),
+                    actual: arguments.len(),
+                    span: name_span(),
+                });
+            }
+            i = close + 1;
+        }
+        if definition.implicit_x {
+            arguments.push(
+                match parameters.iter().position(|parameter| parameter == "x") {
+                    Some(index) => vec![Segment::Parameter(index)],
+                    None => {
+                        let mut x = Mapped::default();
+                        x.push_str("x", origin);
+                        vec![Segment::Text(x)]
+                    }
+                },
+            );
+        }
+        out.substitute(&definition.body, &arguments, origin);
+
+        if out.len > MAX_EXPANDED_LEN {
+            return Err(ShaperError::Unsupported {
+                message: "The script expands to an expression that is too long".to_owned(),
+                span: name_span(),
+            });
+        }
+    }
+    Ok(out.finish())
+}
+
+/// Joins the segments of an expression that has no parameters.
+fn flatten(segments: Vec<Segment>) -> Mapped {
+    let mut expression = Mapped::default();
+    for segment in segments {
+        if let Segment::Text(text) = segment {
+            expression.append(&text);
+        }
+    }
+    expression
+}
+
+#[cfg(test)]
+mod tests {
+    use evalexpr::{build_operator_tree, ContextWithMutableVariables, Value};
+
+    use super::*;
+    use crate::shaper::Shaper;
+
+    /// Expands `source` and evaluates it at `x`.
+    fn eval(source: &str, x: f64) -> f64 {
+        let expanded = expand(source, false).unwrap();
+        let mut context = Shaper::default_context();
+        context.set_value("x".to_owned(), Value::Float(x)).unwrap();
+        build_operator_tree(expanded.text())
+            .unwrap_or_else(|err| panic!("{} doesn't parse: {err}", expanded.text()))
+            .eval_number_with_context(&context)
+            .unwrap()
+    }
+
+    /// Expands `source`, which has to fail, and returns the error message and where it points.
+    fn error(source: &str) -> (String, Range<usize>) {
+        let error = expand(source, false).unwrap_err();
+        // Formatting the error slices the source at the span
+        error.annotate(source);
+        let range = error.span().expect("Error without a span").range.clone();
+        (error.to_string(), range)
+    }
+
+    #[test]
+    fn plain_expressions() {
+        assert_eq!(expand("x * 2", false).unwrap().text(), "x * 2");
+        assert_eq!(eval("\n  x - 1\n", 3.0), 2.0);
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(eval("# Doubles the input\nx * 2 # twice", 0.5), 1.0);
+        assert_eq!(eval("let k = 3 # gain\n# nothing here\nk * x", 0.5), 1.5);
+    }
+
+    #[test]
+    fn variables() {
+        assert_eq!(eval("let k = 3\nlet m = k + 1\nm * x", 0.5), 2.0);
+        // Variables that use `x` see the input of the curve
+        assert_eq!(eval("let square = x * x\nsquare + 1", 2.0), 5.0);
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(eval("f(t) = t * t\nf(x + 1)", 1.0), 4.0);
+        assert_eq!(eval("let f(t) = t * t\nf(x + 1)", 1.0), 4.0);
+        assert_eq!(
+            eval("g(a, b) = a - b\nh(t) = g(t, 1) * 2\nh(x) + g(2, 3)", 3.0),
+            3.0
+        );
+        // Arguments are parenthesized, so they keep their precedence
+        assert_eq!(eval("f(t) = t * 2\nf(x - 1)", 3.0), 4.0);
+        // Commas in nested calls don't split the arguments
+        assert_eq!(eval("g(a, b) = a - b\ng(max(x, 1), min(2, 3))", 0.0), -1.0);
+    }
+
+    #[test]
+    fn functions_see_x() {
+        assert_eq!(eval("f(a) = a * x\nf(2)", 3.0), 6.0);
+        // A parameter called `x` replaces the input
+        assert_eq!(eval("f(x) = x * 2\nf(5)", 3.0), 10.0);
+        // Uses inside of other definitions pass on their `x`
+        assert_eq!(eval("f(a) = a * x\ng(x) = f(1)\ng(4)", 3.0), 4.0);
+    }
+
+    #[test]
+    fn multi_line_statements() {
+        assert_eq!(eval("f(t) = (t +\n    1)\nf(x)", 1.0), 2.0);
+        assert_eq!(eval("max(x,\n    0)", -1.0), 0.0);
+    }
+
+    #[test]
+    fn non_ascii_arguments() {
+        let expanded = expand("f(t) = t * 2\nf(x·2)", false).unwrap();
+        assert!(expanded.text().contains("x·2"));
+        let expanded = expand("f(t) = t * 2\nf(α, β)", false);
+        assert!(matches!(
+            expanded,
+            Err(ShaperError::ArgumentCount { actual: 2, .. })
+        ));
+        let expanded = expand("let α = 2\nf(t) = t * α\nf(\"ä\")", false).unwrap();
+        assert!(expanded.text().contains("\"ä\""));
+    }
+
+    #[test]
+    fn non_ascii_in_malformed_definitions() {
+        let (_, range) = error("let f(·) = 1\nx");
+        assert_eq!(range, 6..8);
+        let (_, range) = error("let f(a·) = 1\nx");
+        assert_eq!(range, 7..9);
+    }
+
+    #[test]
+    fn errors_point_at_the_source() {
+        let (message, range) = error("let k = 1\nlet k = 2\nx");
+        assert!(message.contains("already defined"), "{message}");
+        assert_eq!(range, 14..15);
+
+        let (message, range) = error("# Comment\nf(t, t) = t\nf(x, 1)");
+        assert!(message.contains("Duplicate parameter"), "{message}");
+        assert_eq!(range, 15..16);
+
+        let (_, range) = error("f(t) = t\n\nf(1, 2)");
+        assert_eq!(range, 10..11);
+    }
+
+    #[test]
+    fn invalid_scripts() {
+        let (message, _) = error("x\nlet k = 1");
+        assert!(message.contains("before the curve expression"), "{message}");
+        let (message, _) = error("let a = 1\nx");
+        assert!(message.contains("can't be redefined"), "{message}");
+        let (message, _) = error("let x = 1\nx");
+        assert!(message.contains("can't be redefined"), "{message}");
+        let (message, _) = error("let k =\nx");
+        assert!(message.contains("missing its body"), "{message}");
+        let (message, _) = error("let k = 1");
+        assert!(message.contains("needs an expression"), "{message}");
+        let (message, _) = error("let f() = 1\nx");
+        assert!(message.contains("parameter name"), "{message}");
+        let (message, _) = error("f(t) = t\nf");
+        assert!(message.contains("argument"), "{message}");
+        let (message, _) = error("f(t) = t\nf(x");
+        assert!(message.contains("Unclosed"), "{message}");
+    }
+
+    #[test]
+    fn expansion_is_limited() {
+        let source = "f(t) = t + t + t + t + t + t + t + t\n\
+                      g(t) = f(f(f(t)))\n\
+                      h(t) = g(g(g(t)))\n\
+                      h(x)";
+        assert!(matches!(
+            expand(source, false),
+            Err(ShaperError::Unsupported { .. })
+        ));
+    }
+
+    #[test]
+    fn assignments_are_left_to_evalexpr() {
+        // Without `let` only functions are definitions
+        assert_eq!(
+            expand("y = 2; y * x", false).unwrap().text(),
+            "y = 2; y * x"
+        );
+    }
+
+    /// Expands the piecewise curve `source` and evaluates it at `x`.
+    fn eval_pieces(source: &str, continuous: bool, x: f64) -> f64 {
+        let expanded = expand(source, continuous).unwrap();
+        let mut context = Shaper::default_context();
+        context.set_value("x".to_owned(), Value::Float(x)).unwrap();
+        build_operator_tree(expanded.text())
+            .unwrap()
+            .eval_number_with_context(&context)
+            .unwrap()
+    }
+
+    #[test]
+    fn pieces() {
+        let source = "x < -0.5: -0.5\n-0.5..0.5: x\n> 0.5: 0.5";
+        for (x, expected) in [
+            (-2.0, -0.5),
+            (-0.5, -0.5),
+            (0.25, 0.25),
+            (0.5, 0.5),
+            (3.0, 0.5),
+        ] {
+            assert_eq!(eval_pieces(source, false, x), expected, "at {x}");
+        }
+        // The order of the pieces doesn't matter
+        let source = "> 0: x\n< 0: -x";
+        assert_eq!(eval_pieces(source, false, -2.0), 2.0);
+        assert_eq!(eval_pieces(source, false, 3.0), 3.0);
+    }
+
+    #[test]
+    fn interval_notations() {
+        for source in [
+            "x <= 0: -1\n0 < x < 1: x\nx >= 1: 1",
+            "< 0: -1\n1 > x > 0: x\n> 1: 1",
+            "< 0: -1\n0..1: x\n> 1: 1",
+        ] {
+            assert_eq!(eval_pieces(source, false, -5.0), -1.0, "{source}");
+            assert_eq!(eval_pieces(source, false, 0.5), 0.5, "{source}");
+            assert_eq!(eval_pieces(source, false, 5.0), 1.0, "{source}");
+        }
+    }
+
+    #[test]
+    fn pieces_with_definitions() {
+        let source = "# Half wave\nf(t) = t * 2\nlet k = 3\n< 0: f(x)\n> 0: k x";
+        assert_eq!(eval_pieces(source, false, -1.0), -2.0);
+        assert_eq!(eval_pieces(source, false, 1.0), 3.0);
+    }
+
+    #[test]
+    fn continuity() {
+        let source = "< 0: x - 1\n> 0: x + 1";
+        assert_eq!(eval_pieces(source, false, -2.0), -3.0);
+        // The piece starting at zero stays, the other one gets shifted to meet it
+        assert_eq!(eval_pieces(source, true, -2.0), -1.0);
+        assert_eq!(eval_pieces(source, true, 2.0), 3.0);
+
+        // Offsets accumulate outwards from the piece around zero
+        let source = "< -1: 2\n-1..1: x\n1..2: 5\n> 2: x";
+        assert_eq!(eval_pieces(source, true, -3.0), -1.0);
+        assert_eq!(eval_pieces(source, true, 0.5), 0.5);
+        assert_eq!(eval_pieces(source, true, 1.5), 1.0);
+        assert_eq!(eval_pieces(source, true, 3.0), 2.0);
+    }
+
+    #[test]
+    fn paths_are_not_pieces() {
+        assert_eq!(
+            expand("math::sin(x)", false).unwrap().text(),
+            "math::sin(x)"
+        );
+    }
+
+    #[test]
+    fn invalid_pieces() {
+        let (message, range) = error("< 0: x\n> 1: x");
+        assert!(message.contains("without gaps"), "{message}");
+        assert_eq!(range, 7..8);
+        let (message, _) = error("< 1: x\n> 0: x");
+        assert!(message.contains("without gaps"), "{message}");
+        let (message, _) = error("1..0: x");
+        assert!(message.contains("empty"), "{message}");
+        let (message, range) = error("a..b: x");
+        assert!(message.contains("Expected an interval"), "{message}");
+        assert_eq!(range, 0..4);
+        let (message, _) = error("< 0:\n> 0: x");
+        assert!(message.contains("missing its expression"), "{message}");
+        let (message, _) = error("< 0: x\nlet k = 1\n> 0: x");
+        assert!(message.contains("before the curve expression"), "{message}");
+        let (message, range) = error("π\n< 0: 1\n> 0: 2");
+        assert!(message.contains("only consist of pieces"), "{message}");
+        assert_eq!(range, 0..2);
+    }
+}
@@ -0,0 +1,362 @@
+use std::ops::Range;
+
+use super::script::{is_identifier_char, is_identifier_start, string_end, Mapped};
+
+/// The functions from evalexpr's `math::` namespace that can also be called without it.
+const MATH_FUNCTIONS: &[&str] = &[
+    "ln", "log", "log2", "log10", "exp", "exp2", "pow", "cos", "acos", "cosh", "acosh", "sin",
+    "asin", "sinh", "asinh", "tan", "atan", "tanh", "atanh", "atan2", "sqrt", "cbrt", "hypot",
+    "abs",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Number,
+    Identifier,
+    /// `π`, which is its own token even right next to an identifier.
+    Pi,
+    String,
+    Open,
+    Close,
+    Power,
+    /// A `+` or `-`, which can be a sign.
+    Sign,
+    Whitespace,
+    Other,
+}
+
+#[derive(Debug)]
+struct Token {
+    kind: Kind,
+    range: Range<usize>,
+}
+
+/// Rewrites the more common math notation in `text` into evalexpr syntax:
+///
+/// - Functions from `math::` can be called without the prefix, unless `is_defined` says the script
+///   defines something with the same name.
+/// - `^` is right associative, so `2^3^2` is `2^(3^2)`.
+/// - Multiplication can be implicit, as in `3x`, `2(x + 1)` or `(x - 1)(x + 1)`. An identifier
+///   followed by parentheses is a function call unless `is_variable` says it's a variable.
+/// - `π` is `PI`, and can be multiplied implicitly as well, as in `2πx`.
+///
+/// Everything that is inserted maps to the position of the token it belongs to, so errors still
+/// point at the original text.
+pub fn preprocess(
+    text: &Mapped,
+    is_variable: &dyn Fn(&str) -> bool,
+    is_defined: &dyn Fn(&str) -> bool,
+) -> Mapped {
+    let raw = text.text();
+    let tokens = tokenize(raw);
+    let significant: Vec<usize> = (0..tokens.len())
+        .filter(|&i| tokens[i].kind != Kind::Whitespace)
+        .collect();
+
+    // Parentheses that make `^` right associative, counted per token
+    let mut opens = vec![0; tokens.len()];
+    let mut closes = vec![0; tokens.len()];
+    for (position, &i) in significant.iter().enumerate() {
+        if tokens[i].kind != Kind::Power {
+            continue;
+        }
+        let mut end = operand_end(raw, &tokens, &significant, position + 1, is_variable);
+        let mut chained = false;
+        while let Some(next) = end.filter(|&end| {
+            significant
+                .get(end + 1)
+                .is_some_and(|&j| tokens[j].kind == Kind::Power)
+        }) {
+            chained = true;
+            end = operand_end(raw, &tokens, &significant, next + 2, is_variable);
+        }
+        if let (true, Some(end)) = (chained, end) {
+            opens[significant[position + 1]] += 1;
+            closes[significant[end]] += 1;
+        }
+    }
+
+    let mut result = Mapped::default();
+    let mut previous: Option<&Token> = None;
+    // The whitespace since the previous significant token, repeated after an inserted `*` so
+    // `a x` becomes `a * x`
+    let mut gap = "";
+    for (i, token) in tokens.iter().enumerate() {
+        let origin = text.origin(token.range.start);
+        let source = &raw[token.range.clone()];
+        if token.kind == Kind::Whitespace {
+            gap = source;
+        } else {
+            if previous
+                .is_some_and(|previous| implicit_multiplication(raw, previous, token, is_variable))
+            {
+                result.push_str("*", origin);
+                result.push_str(gap, origin);
+            }
+            previous = Some(token);
+            gap = "";
+        }
+
+        for _ in 0..opens[i] {
+            result.push_str("(", origin);
+        }
+        match token.kind {
+            Kind::Pi => result.push_str("PI", origin),
+            Kind::Identifier if MATH_FUNCTIONS.contains(&source) && !is_defined(source) => {
+                result.push_str("math::", origin);
+                result.append(&text.slice(token.range.clone()));
+            }
+            _ => result.append(&text.slice(token.range.clone())),
+        }
+        for _ in 0..closes[i] {
+            result.push_str(")", text.origin(token.range.end - 1));
+        }
+    }
+    result
+}
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < text.len() {
+        let c = text[i..].chars().next().expect("Not at a char boundary");
+        let (kind, end) = match c {
+            '"' => (Kind::String, string_end(text, i)),
+            '(' => (Kind::Open, i + 1),
+            ')' => (Kind::Close, i + 1),
+            '^' => (Kind::Power, i + 1),
+            '+' | '-' => (Kind::Sign, i + 1),
+            'π' => (Kind::Pi, i + 'π'.len_utf8()),
+            c if c.is_ascii_digit() => (Kind::Number, number_end(text, i)),
+            c if is_identifier_start(c) => (
+                Kind::Identifier,
+                text[i..]
+                    .find(|c: char| !is_identifier_char(c) || c == 'π')
+                    .map_or(text.len(), |end| i + end),
+            ),
+            c if c.is_whitespace() => (
+                Kind::Whitespace,
+                text[i..]
+                    .find(|c: char| !c.is_whitespace())
+                    .map_or(text.len(), |end| i + end),
+            ),
+            c => (Kind::Other, i + c.len_utf8()),
+        };
+        tokens.push(Token {
+            kind,
+            range: i..end,
+        });
+        i = end;
+    }
+    tokens
+}
+
+/// The end of the number starting at `start`. An `e` only belongs to the number if an exponent
+/// follows, so `2e` is two times `e`.
+fn number_end(text: &str, start: usize) -> usize {
+    let digits_end = |from: usize| {
+        text[from..]
+            .find(|c: char| !c.is_ascii_digit())
+            .map_or(text.len(), |end| from + end)
+    };
+
+    let mut end = digits_end(start);
+    if text[end..].starts_with('.') && text[end + 1..].starts_with(|c: char| c.is_ascii_digit()) {
+        end = digits_end(end + 1);
+    }
+    if text[end..].starts_with(['e', 'E']) {
+        let exponent = if text[end + 1..].starts_with(['+', '-']) {
+            end + 2
+        } else {
+            end + 1
+        };
+        if text[exponent..].starts_with(|c: char| c.is_ascii_digit()) {
+            end = digits_end(exponent);
+        }
+    }
+    end
+}
+
+/// Whether a `*` belongs between `left` and `right`, which are adjacent apart from whitespace.
+fn implicit_multiplication(
+    text: &str,
+    left: &Token,
+    right: &Token,
+    is_variable: &dyn Fn(&str) -> bool,
+) -> bool {
+    match (left.kind, right.kind) {
+        (Kind::Number, Kind::Identifier | Kind::Pi | Kind::Open) => true,
+        (Kind::Close | Kind::Pi, Kind::Number | Kind::Identifier | Kind::Pi | Kind::Open) => true,
+        (Kind::Identifier, Kind::Number | Kind::Identifier | Kind::Pi | Kind::Open) => {
+            is_variable(&text[left.range.clone()])
+        }
+        _ => false,
+    }
+}
+
+/// The index into `significant` of the last token of the operand of `^` that starts at
+/// `start`, which is a signed number, `π`, variable, call or parenthesized expression. `None` if
+/// there's no complete operand.
+fn operand_end(
+    text: &str,
+    tokens: &[Token],
+    significant: &[usize],
+    start: usize,
+    is_variable: &dyn Fn(&str) -> bool,
+) -> Option<usize> {
+    let mut position = start;
+    while tokens[*significant.get(position)?].kind == Kind::Sign {
+        position += 1;
+    }
+
+    let token = &tokens[significant[position]];
+    match token.kind {
+        Kind::Number | Kind::Pi => Some(position),
+        Kind::Identifier => {
+            let is_call = significant
+                .get(position + 1)
+                .is_some_and(|&next| tokens[next].kind == Kind::Open);
+            // Keeps `a^b(c)` as `a^b * (c)` for variables, just like without the power
+            if is_call && !is_variable(&text[token.range.clone()]) {
+                closing(tokens, significant, position + 1)
+            } else {
+                Some(position)
+            }
+        }
+        Kind::Open => closing(tokens, significant, position),
+        _ => None,
+    }
+}
+
+/// The index into `significant` of the parenthesis closing the one at `open`.
+fn closing(tokens: &[Token], significant: &[usize], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (position, &i) in significant.iter().enumerate().skip(open) {
+        match tokens[i].kind {
+            Kind::Open => depth += 1,
+            Kind::Close => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(position);
+                }
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use evalexpr::{build_operator_tree, ContextWithMutableVariables, Value};
+
+    use super::*;
+    use crate::shaper::Shaper;
+
+    fn mapped(text: &str) -> Mapped {
+        let mut mapped = Mapped::default();
+        mapped.push_source(text, 0..text.len());
+        mapped
+    }
+
+    fn is_variable(name: &str) -> bool {
+        matches!(name, "x" | "a" | "PI" | "E")
+    }
+
+    fn rewrite(text: &str) -> String {
+        preprocess(&mapped(text), &is_variable, &|_| false)
+            .text()
+            .to_owned()
+    }
+
+    fn eval(text: &str, x: f64) -> f64 {
+        let mut context = Shaper::default_context();
+        context.set_value("x".to_owned(), Value::Float(x)).unwrap();
+        build_operator_tree(&rewrite(text))
+            .unwrap()
+            .eval_number_with_context(&context)
+            .unwrap()
+    }
+
+    #[test]
+    fn pi() {
+        assert_eq!(rewrite("π"), "PI");
+        assert_eq!(rewrite("2πx"), "2*PI*x");
+        assert_eq!(rewrite("πx"), "PI*x");
+        assert_eq!(rewrite("xπ"), "x*PI");
+        assert_eq!(rewrite("3π(x+1)"), "3*PI*(x+1)");
+        assert_eq!(rewrite("sin(3πx)"), "math::sin(3*PI*x)");
+        assert_eq!(rewrite("2^π^2"), "2^(PI^2)");
+
+        let pi = std::f64::consts::PI;
+        assert_eq!(eval("2πx", 0.25), 2.0 * pi * 0.25);
+        assert_eq!(eval("sin(3πx)", 0.5), (3.0 * pi * 0.5).sin());
+        assert_eq!(eval("3π(x+1)", 1.0), 3.0 * pi * 2.0);
+    }
+
+    #[test]
+    fn pi_maps_to_its_origin() {
+        let rewritten = preprocess(&mapped("2πx"), &is_variable, &|_| false);
+        assert_eq!(rewritten.text(), "2*PI*x");
+        let origins: Vec<usize> = (0..rewritten.text().len())
+            .map(|i| rewritten.origin(i))
+            .collect();
+        assert_eq!(origins, [0, 1, 1, 1, 3, 3]);
+    }
+
+    #[test]
+    fn math_prefix() {
+        assert_eq!(
+            rewrite("sin(x) + math::cos(x)"),
+            "math::sin(x) + math::cos(x)"
+        );
+        assert_eq!(rewrite("tanh(2x)"), "math::tanh(2*x)");
+        // Only for functions the script doesn't define itself
+        let rewritten = preprocess(&mapped("sin(x) + cos(x)"), &is_variable, &|name| {
+            name == "sin"
+        });
+        assert_eq!(rewritten.text(), "sin(x) + math::cos(x)");
+        // Names that merely start like one are left alone
+        assert_eq!(rewrite("sinc(x)"), "sinc(x)");
+    }
+
+    #[test]
+    fn powers_group_from_the_right() {
+        assert_eq!(rewrite("x^2"), "x^2");
+        assert_eq!(rewrite("2^3^2"), "2^(3^2)");
+        assert_eq!(rewrite("2^3^2^2"), "2^(3^(2^2))");
+        assert_eq!(rewrite("(x + 1)^2^a"), "(x + 1)^(2^a)");
+        assert_eq!(rewrite("2^-x^2"), "2^(-x^2)");
+        assert_eq!(rewrite("E^sin(x)^2"), "E^(math::sin(x)^2)");
+        assert_eq!(eval("2^3^2", 0.0), 512.0);
+    }
+
+    #[test]
+    fn implicit_multiplication() {
+        assert_eq!(rewrite("3x"), "3*x");
+        assert_eq!(rewrite("1.5x + 2e3x"), "1.5*x + 2e3*x");
+        assert_eq!(rewrite("2(x + 1)"), "2*(x + 1)");
+        assert_eq!(rewrite("(x - 1)(x + 1)"), "(x - 1)*(x + 1)");
+        assert_eq!(rewrite("(x)2"), "(x)*2");
+        assert_eq!(rewrite("a x"), "a * x");
+        assert_eq!(rewrite("2  (x)"), "2  *  (x)");
+        assert_eq!(rewrite("x(2)"), "x*(2)");
+        // Functions are called, not multiplied
+        assert_eq!(rewrite("f(2)"), "f(2)");
+        assert_eq!(rewrite("3 - 2"), "3 - 2");
+        assert_eq!(eval("(x - 1)(x + 1)", 3.0), 8.0);
+    }
+
+    #[test]
+    fn exponents_belong_to_numbers() {
+        // `E` is Euler's number unless digits follow
+        assert_eq!(rewrite("2E"), "2*E");
+        assert_eq!(rewrite("2E2"), "2E2");
+        assert_eq!(rewrite("2e-3"), "2e-3");
+    }
+
+    #[test]
+    fn strings_are_left_alone() {
+        assert_eq!(rewrite("\"3x ^ π\""), "\"3x ^ π\"");
+    }
+}